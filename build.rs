@@ -0,0 +1,63 @@
+//! Embeds the survey pages, `404.html`, `flow.toml` and everything in `lib/`,
+//! `partials/` and `static/` into the binary, so it runs from any directory. Generates
+//! `$OUT_DIR/assets.rs`, which `src/assets.rs` includes.
+
+use std::env;
+use std::fmt::Write;
+use std::fs;
+use std::path::Path;
+use std::time::UNIX_EPOCH;
+
+/// Directories embedded in full: images, template partials and stylesheets
+const ASSET_DIRS: [&str; 3] = ["lib", "partials", "static"];
+
+fn is_root_asset(name: &str) -> bool {
+    name == "404.html" || name == "flow.toml" || (name.starts_with("page") && name.ends_with(".html"))
+}
+
+fn main() {
+    let root = env::var("CARGO_MANIFEST_DIR").unwrap();
+    let root = Path::new(&root);
+
+    let mut files = Vec::new();
+    for entry in fs::read_dir(root).unwrap() {
+        let name = entry.unwrap().file_name().to_string_lossy().into_owned();
+        if is_root_asset(&name) {
+            files.push(name);
+        }
+    }
+    for dir in ASSET_DIRS {
+        for entry in fs::read_dir(root.join(dir)).unwrap() {
+            let entry = entry.unwrap();
+            if entry.file_type().unwrap().is_file() {
+                files.push(format!("{}/{}", dir, entry.file_name().to_string_lossy()));
+            }
+        }
+    }
+    files.sort();
+
+    // Watching the crate root itself would also watch target/ and rebuild
+    // every time, so only the known files are watched. A new page*.html is
+    // picked up on the next change to any other asset or to this script.
+    println!("cargo:rerun-if-changed=build.rs");
+    for dir in ASSET_DIRS {
+        println!("cargo:rerun-if-changed={}", dir);
+    }
+    let mut out = String::from("static EMBEDDED: &[(&str, &[u8], u64, u32)] = &[\n");
+    for file in &files {
+        let path = root.join(file);
+        println!("cargo:rerun-if-changed={}", file);
+        let mtime = fs::metadata(&path).unwrap().modified().unwrap().duration_since(UNIX_EPOCH).unwrap_or_default();
+        writeln!(
+            out,
+            "    ({:?}, include_bytes!({:?}), {}, {}),",
+            file,
+            path.display().to_string(),
+            mtime.as_secs(),
+            mtime.subsec_nanos()
+        )
+        .unwrap();
+    }
+    out.push_str("];\n");
+    fs::write(Path::new(&env::var("OUT_DIR").unwrap()).join("assets.rs"), out).unwrap();
+}
@@ -0,0 +1,179 @@
+/// Credentials for the admin routes (`/view-data`, `/clear-data`).
+///
+/// `ADMIN_TOKEN` is accepted as `Authorization: Bearer <token>` or as the
+/// password of HTTP Basic credentials with any username, so it works from
+/// both scripts and browsers. `ADMIN_USER` and `ADMIN_PASSWORD` together
+/// enable a fixed Basic username/password pair. With neither set, admin
+/// routes are disabled.
+#[derive(Debug, Clone, Default)]
+pub struct AdminAuth {
+    token: Option<String>,
+    basic: Option<(String, String)>,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum AuthResult {
+    Allowed,
+    Denied,
+    Disabled,
+}
+
+impl AdminAuth {
+    pub fn from_env() -> Self {
+        let non_empty = |name| std::env::var(name).ok().filter(|v: &String| !v.is_empty());
+        let token = non_empty("ADMIN_TOKEN");
+        let basic = non_empty("ADMIN_USER").zip(non_empty("ADMIN_PASSWORD"));
+        if token.is_none() && basic.is_none() {
+            println!("[WARN] No ADMIN_TOKEN or ADMIN_USER/ADMIN_PASSWORD set; admin routes are disabled");
+        }
+        Self::new(token, basic)
+    }
+
+    /// A token and/or a Basic (user, password) pair; neither disables the routes
+    pub fn new(token: Option<String>, basic: Option<(String, String)>) -> Self {
+        Self { token, basic }
+    }
+
+    /// Checks the value of a request's `Authorization` header
+    pub fn check(&self, authorization: Option<&str>) -> AuthResult {
+        if self.token.is_none() && self.basic.is_none() {
+            return AuthResult::Disabled;
+        }
+        match authorization.map(|h| self.accepts(h)) {
+            Some(true) => AuthResult::Allowed,
+            _ => AuthResult::Denied,
+        }
+    }
+
+    fn accepts(&self, header: &str) -> bool {
+        let (scheme, credentials) = header.split_once(' ').unwrap_or((header, ""));
+        let credentials = credentials.trim();
+        if scheme.eq_ignore_ascii_case("bearer") {
+            return self.token.as_deref().is_some_and(|token| constant_time_eq(credentials, token));
+        }
+        if !scheme.eq_ignore_ascii_case("basic") {
+            return false;
+        }
+        let Some(decoded) = base64_decode(credentials).and_then(|bytes| String::from_utf8(bytes).ok()) else {
+            return false;
+        };
+        let Some((user, password)) = decoded.split_once(':') else {
+            return false;
+        };
+        let token_ok = self.token.as_deref().is_some_and(|token| constant_time_eq(password, token));
+        let basic_ok = self.basic.as_ref().is_some_and(|(expected_user, expected_password)| {
+            // Evaluate both so timing doesn't reveal which half was wrong
+            constant_time_eq(user, expected_user) & constant_time_eq(password, expected_password)
+        });
+        token_ok || basic_ok
+    }
+}
+
+/// Compares two secrets without returning early on the first mismatch
+fn constant_time_eq(a: &str, b: &str) -> bool {
+    let (a, b) = (a.as_bytes(), b.as_bytes());
+    let mut diff = a.len() ^ b.len();
+    for i in 0..a.len().max(b.len()) {
+        let x = a.get(i).copied().unwrap_or(0);
+        let y = b.get(i).copied().unwrap_or(0);
+        diff |= usize::from(x ^ y);
+    }
+    diff == 0
+}
+
+fn base64_decode(input: &str) -> Option<Vec<u8>> {
+    fn value(c: u8) -> Option<u32> {
+        match c {
+            b'A'..=b'Z' => Some(u32::from(c - b'A')),
+            b'a'..=b'z' => Some(u32::from(c - b'a') + 26),
+            b'0'..=b'9' => Some(u32::from(c - b'0') + 52),
+            b'+' => Some(62),
+            b'/' => Some(63),
+            _ => None,
+        }
+    }
+    let input = input.trim_end_matches('=').as_bytes();
+    let mut out = Vec::with_capacity(input.len() * 3 / 4);
+    for chunk in input.chunks(4) {
+        if chunk.len() == 1 {
+            return None;
+        }
+        let mut bits = 0u32;
+        for (i, &c) in chunk.iter().enumerate() {
+            bits |= value(c)? << (18 - 6 * i);
+        }
+        let bytes = bits.to_be_bytes();
+        out.extend_from_slice(&bytes[1..chunk.len()]);
+    }
+    Some(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn auth() -> AdminAuth {
+        AdminAuth::new(Some("tok123".to_string()), Some(("admin".to_string(), "secret".to_string())))
+    }
+
+    #[test]
+    fn accepts_the_token_as_bearer_or_basic_password() {
+        let auth = auth();
+        assert_eq!(auth.check(Some("Bearer tok123")), AuthResult::Allowed);
+        assert_eq!(auth.check(Some("bearer  tok123 ")), AuthResult::Allowed);
+        assert_eq!(auth.check(Some("Bearer tok12")), AuthResult::Denied);
+        assert_eq!(auth.check(Some("Bearer tok1234")), AuthResult::Denied);
+        assert_eq!(auth.check(Some("Bearer")), AuthResult::Denied);
+        // "anyone:tok123"
+        assert_eq!(auth.check(Some("Basic YW55b25lOnRvazEyMw==")), AuthResult::Allowed);
+        assert_eq!(auth.check(None), AuthResult::Denied);
+    }
+
+    #[test]
+    fn accepts_only_the_configured_basic_pair() {
+        let auth = auth();
+        // "admin:secret", "admin:wrong", "root:secret", "admin:secre"
+        assert_eq!(auth.check(Some("Basic YWRtaW46c2VjcmV0")), AuthResult::Allowed);
+        assert_eq!(auth.check(Some("Basic YWRtaW46d3Jvbmc=")), AuthResult::Denied);
+        assert_eq!(auth.check(Some("Basic cm9vdDpzZWNyZXQ=")), AuthResult::Denied);
+        assert_eq!(auth.check(Some("Basic YWRtaW46c2VjcmU=")), AuthResult::Denied);
+        // The Basic password is not a bearer token
+        assert_eq!(auth.check(Some("Bearer secret")), AuthResult::Denied);
+        assert_eq!(auth.check(Some("Digest YWRtaW46c2VjcmV0")), AuthResult::Denied);
+        assert_eq!(auth.check(Some("YWRtaW46c2VjcmV0")), AuthResult::Denied);
+    }
+
+    #[test]
+    fn rejects_malformed_basic_credentials() {
+        let auth = auth();
+        for header in ["Basic", "Basic !!!!", "Basic YWRtaW46c2VjcmV0Y", "Basic YWRtaW46c2Vj cmV0", "Basic YWRtaW4=", "Basic /w=="] {
+            assert_eq!(auth.check(Some(header)), AuthResult::Denied, "{}", header);
+        }
+        assert_eq!(base64_decode("YTpi"), Some(b"a:b".to_vec()));
+        assert_eq!(base64_decode("YWRtaW46c2VjcmU="), Some(b"admin:secre".to_vec()));
+        assert_eq!(base64_decode("YWRtaW46c2VjcmU"), Some(b"admin:secre".to_vec()));
+        assert_eq!(base64_decode("YTpiY"), None);
+        assert_eq!(base64_decode("YT-i"), None);
+    }
+
+    #[test]
+    fn disabled_without_credentials() {
+        let auth = AdminAuth::new(None, None);
+        for header in [None, Some("Bearer tok123"), Some("Basic YWRtaW46c2VjcmV0")] {
+            assert_eq!(auth.check(header), AuthResult::Disabled);
+        }
+        let token_only = AdminAuth::new(Some("tok123".to_string()), None);
+        assert_eq!(token_only.check(Some("Basic YWRtaW46c2VjcmV0")), AuthResult::Denied);
+    }
+
+    #[test]
+    fn compares_secrets_of_any_length() {
+        assert!(constant_time_eq("", ""));
+        assert!(constant_time_eq("secret", "secret"));
+        assert!(!constant_time_eq("secret", "secre"));
+        assert!(!constant_time_eq("secre", "secret"));
+        assert!(!constant_time_eq("", "secret"));
+        // A shorter input is not padded with NULs into a match
+        assert!(!constant_time_eq("ab", "ab\0"));
+    }
+}
@@ -0,0 +1,107 @@
+use crate::timestamp;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+/// A timestamped copy of the response CSV under the archive directory
+#[derive(Debug)]
+pub struct Snapshot {
+    pub name: String,
+    pub size: u64,
+}
+
+/// Moves the current CSV into `archive_dir` as `data-<timestamp>.csv` and
+/// returns the snapshot name. Fails if the CSV does not exist.
+pub fn archive_current(csv_path: &Path, archive_dir: &Path) -> io::Result<String> {
+    let name = unused_name(archive_dir)?;
+    fs::rename(csv_path, archive_dir.join(&name))?;
+    Ok(name)
+}
+
+/// Writes `csv` into `archive_dir` as a new snapshot and returns its name
+pub fn write_snapshot(archive_dir: &Path, csv: &str) -> io::Result<String> {
+    let name = unused_name(archive_dir)?;
+    fs::write(archive_dir.join(&name), csv)?;
+    Ok(name)
+}
+
+/// A `data-<timestamp>.csv` name not yet taken, creating the directory if needed
+fn unused_name(archive_dir: &Path) -> io::Result<String> {
+    fs::create_dir_all(archive_dir)?;
+    let stamp = timestamp::format_compact(SystemTime::now());
+    let mut name = format!("data-{}.csv", stamp);
+    // Two archives in the same second get a numeric suffix
+    let mut n = 1;
+    while archive_dir.join(&name).exists() {
+        name = format!("data-{}-{}.csv", stamp, n);
+        n += 1;
+    }
+    Ok(name)
+}
+
+/// All snapshots, newest first
+pub fn list(archive_dir: &Path) -> io::Result<Vec<Snapshot>> {
+    let mut snapshots = Vec::new();
+    let entries = match fs::read_dir(archive_dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(snapshots),
+        Err(e) => return Err(e),
+    };
+    for entry in entries {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if is_snapshot_name(&name) {
+            snapshots.push(Snapshot { name, size: entry.metadata()?.len() });
+        }
+    }
+    snapshots.sort_by(|a, b| b.name.cmp(&a.name));
+    Ok(snapshots)
+}
+
+/// Path of a snapshot, if `name` is a well-formed snapshot name that exists.
+/// Names come from the URL, so anything else (including `..`) is rejected.
+pub fn snapshot_path(archive_dir: &Path, name: &str) -> Option<PathBuf> {
+    if !is_snapshot_name(name) {
+        return None;
+    }
+    let path = archive_dir.join(name);
+    path.is_file().then_some(path)
+}
+
+/// Replaces the current CSV with a snapshot, as rewritten by `prepare`. The
+/// current file is archived first, so a restore never loses data; if
+/// `prepare` fails, nothing changes. Returns the name the current file was
+/// archived under, if there was one.
+///
+/// Between archiving the current file and moving the snapshot in there is no
+/// file at `csv_path`, so callers must keep writers out until this returns.
+pub fn restore(
+    csv_path: &Path,
+    archive_dir: &Path,
+    name: &str,
+    prepare: impl FnOnce(&str) -> io::Result<String>,
+) -> io::Result<Option<String>> {
+    let source = snapshot_path(archive_dir, name)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("no snapshot named {}", name)))?;
+    let contents = prepare(&fs::read_to_string(source)?)?;
+    // Write next to the target first, so the file is only missing for the two renames
+    // and never appears half-written
+    let staging = csv_path.with_extension("csv.restoring");
+    fs::write(&staging, contents)?;
+    let archived = if csv_path.exists() {
+        Some(archive_current(csv_path, archive_dir)?)
+    } else {
+        None
+    };
+    fs::rename(&staging, csv_path)?;
+    Ok(archived)
+}
+
+fn is_snapshot_name(name: &str) -> bool {
+    name.strip_prefix("data-")
+        .and_then(|rest| rest.strip_suffix(".csv"))
+        .is_some_and(|stamp| {
+            !stamp.is_empty() && stamp.bytes().all(|b| b.is_ascii_digit() || b == b'T' || b == b'Z' || b == b'-')
+        })
+}
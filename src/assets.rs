@@ -0,0 +1,59 @@
+use std::borrow::Cow;
+use std::fs;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+// Generated by build.rs: (path, contents, mtime secs, mtime nanos) for every
+// page, 404.html, flow.toml and file under lib/, partials/ and static/
+include!(concat!(env!("OUT_DIR"), "/assets.rs"));
+
+/// A page, image or other bundled file
+pub struct Asset {
+    pub bytes: Cow<'static, [u8]>,
+    pub modified: SystemTime,
+}
+
+/// Where pages, images and `flow.toml` come from: the copies embedded at
+/// build time, or (in dev mode) the files on disk so edits show up without
+/// a rebuild
+#[derive(Debug)]
+pub enum Assets {
+    Embedded,
+    Disk(PathBuf),
+}
+
+impl Assets {
+    /// The file at `path` relative to the asset root, e.g. `lib/trophy.png`.
+    /// On disk, paths that resolve outside the root are treated as missing.
+    pub fn get(&self, path: &str) -> Option<Asset> {
+        match self {
+            Assets::Embedded => EMBEDDED.iter().find(|(name, ..)| *name == path).map(|&(_, bytes, secs, nanos)| Asset {
+                bytes: Cow::Borrowed(bytes),
+                modified: UNIX_EPOCH + Duration::new(secs, nanos),
+            }),
+            Assets::Disk(root) => {
+                let root = root.canonicalize().ok()?;
+                let file = root.join(path).canonicalize().ok()?;
+                if !file.starts_with(&root) || !file.is_file() {
+                    return None;
+                }
+                let modified = fs::metadata(&file).and_then(|m| m.modified()).unwrap_or(UNIX_EPOCH);
+                let bytes = fs::read(&file).ok()?;
+                Some(Asset { bytes: Cow::Owned(bytes), modified })
+            }
+        }
+    }
+
+    /// A text asset such as a page or `flow.toml`
+    pub fn get_string(&self, path: &str) -> Option<String> {
+        self.get(path).map(|asset| String::from_utf8_lossy(&asset.bytes).into_owned())
+    }
+
+    /// Where assets are read from, for logging
+    pub fn describe(&self) -> Cow<'static, str> {
+        match self {
+            Assets::Embedded => Cow::Borrowed("embedded assets"),
+            Assets::Disk(root) => Cow::Owned(format!("files under {}", root.display())),
+        }
+    }
+}
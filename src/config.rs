@@ -0,0 +1,319 @@
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// Server settings. Each one can come from an environment variable or a
+/// command-line flag; flags win over the environment.
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub host: IpAddr,
+    pub port: u16,
+    /// Directory for the response CSV, archive and session journal
+    pub data_dir: PathBuf,
+    /// Directory holding the HTML pages, `lib/` and `flow.toml`, read in dev mode
+    pub static_root: PathBuf,
+    /// Read assets from `static_root` on every request instead of using the
+    /// copies embedded at build time
+    pub dev_mode: bool,
+    /// Connections served at once, one worker thread each
+    pub max_connections: usize,
+    pub session_ttl: Duration,
+    /// Whether evicted sessions are written to the CSV as abandoned
+    pub log_abandoned: bool,
+    /// Where responses are recorded
+    pub storage: Storage,
+}
+
+/// Backend for recorded responses
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Storage {
+    /// `data.csv`, one line per response
+    Csv,
+    /// `responses.db`, see `response_db`
+    Db,
+}
+
+impl Storage {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Storage::Csv => "csv",
+            Storage::Db => "db",
+        }
+    }
+}
+
+/// What the command line asked for
+#[derive(Debug)]
+pub enum Command {
+    Serve(Config),
+    PrintConfig(Config),
+    /// Copy `data.csv` into the response database
+    ImportCsv(Config),
+    Help,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum ConfigError {
+    UnknownFlag(String),
+    MissingValue(String),
+    Invalid { source: String, value: String, reason: &'static str },
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::UnknownFlag(flag) => write!(f, "unknown flag {}", flag),
+            ConfigError::MissingValue(flag) => write!(f, "{} needs a value", flag),
+            ConfigError::Invalid { source, value, reason } => write!(f, "invalid {} {:?}: {}", source, value, reason),
+        }
+    }
+}
+
+/// A setting's flag (without the leading `--`) and environment variable
+struct Setting {
+    flag: &'static str,
+    env: &'static str,
+    help: &'static str,
+    /// A bare flag means `true`
+    boolean: bool,
+}
+
+const HOST: Setting = Setting { flag: "host", env: "HOST", help: "address to bind (default 127.0.0.1, or 0.0.0.0 on Fly.io)", boolean: false };
+const PORT: Setting = Setting { flag: "port", env: "PORT", help: "port to listen on (default 7878, or 8080 on Fly.io)", boolean: false };
+const DATA_DIR: Setting = Setting { flag: "data-dir", env: "DATA_DIR", help: "where responses and sessions are stored (default data, or /data on Fly.io)", boolean: false };
+const STATIC_ROOT: Setting = Setting { flag: "static-root", env: "STATIC_ROOT", help: "directory with the pages, lib/ and flow.toml for dev mode (default .)", boolean: false };
+const DEV_MODE: Setting = Setting { flag: "dev-mode", env: "DEV_MODE", help: "read assets from the static root instead of the binary (default false)", boolean: true };
+const MAX_CONNECTIONS: Setting = Setting { flag: "max-connections", env: "MAX_CONNECTIONS", help: "connections served at once (default 16)", boolean: false };
+const SESSION_TTL: Setting = Setting { flag: "session-ttl-secs", env: "SESSION_TTL_SECS", help: "idle seconds before a session is evicted (default 1800)", boolean: false };
+const LOG_ABANDONED: Setting = Setting { flag: "log-abandoned-sessions", env: "LOG_ABANDONED_SESSIONS", help: "record evicted sessions as abandoned, true or false (default true)", boolean: true };
+
+const STORAGE: Setting = Setting { flag: "storage", env: "STORAGE", help: "where responses are recorded, csv or db (default csv)", boolean: false };
+
+const SETTINGS: [&Setting; 9] = [&HOST, &PORT, &DATA_DIR, &STATIC_ROOT, &DEV_MODE, &MAX_CONNECTIONS, &SESSION_TTL, &LOG_ABANDONED, &STORAGE];
+
+const MAX_CONNECTIONS_LIMIT: usize = 1024;
+
+impl Command {
+    /// Parses `args` (without the program name) on top of the environment,
+    /// as read through `env`
+    pub fn parse<I, E>(args: I, env: E) -> Result<Self, ConfigError>
+    where
+        I: IntoIterator<Item = String>,
+        E: Fn(&str) -> Option<String>,
+    {
+        let mut flags: Vec<(&'static Setting, String)> = Vec::new();
+        let mut print_config = false;
+        let mut import_csv = false;
+        let mut args = args.into_iter().peekable();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--help" | "-h" => return Ok(Command::Help),
+                "--print-config" => {
+                    print_config = true;
+                    continue;
+                }
+                "--import-csv" => {
+                    import_csv = true;
+                    continue;
+                }
+                _ => {}
+            }
+            let (name, inline_value) = match arg.strip_prefix("--") {
+                Some(flag) => flag.split_once('=').map_or((flag, None), |(name, value)| (name, Some(value.to_string()))),
+                None => return Err(ConfigError::UnknownFlag(arg)),
+            };
+            let Some(setting) = SETTINGS.into_iter().find(|s| s.flag == name) else {
+                return Err(ConfigError::UnknownFlag(arg));
+            };
+            let value = match inline_value {
+                Some(value) => value,
+                // `--dev-mode` alone, or followed by another flag, switches it on
+                None if setting.boolean && args.peek().is_none_or(|next| next.starts_with("--")) => "true".to_string(),
+                None => match args.next() {
+                    Some(value) => value,
+                    None => return Err(ConfigError::MissingValue(format!("--{}", setting.flag))),
+                },
+            };
+            flags.push((setting, value));
+        }
+
+        // Later flags override earlier ones, and any flag overrides the environment
+        let lookup = |setting: &Setting| -> Option<(String, String)> {
+            match flags.iter().rev().find(|(s, _)| s.flag == setting.flag) {
+                Some((_, value)) => Some((format!("--{}", setting.flag), value.clone())),
+                None => env(setting.env).filter(|v| !v.is_empty()).map(|v| (setting.env.to_string(), v)),
+            }
+        };
+        let on_fly = env("FLY_APP_NAME").is_some();
+
+        let host = parse_or(lookup(&HOST), if on_fly { IpAddr::V4(Ipv4Addr::UNSPECIFIED) } else { IpAddr::V4(Ipv4Addr::LOCALHOST) }, |v| {
+            v.parse().map_err(|_| "not an IP address")
+        })?;
+        let port = parse_or(lookup(&PORT), if on_fly { 8080 } else { 7878 }, |v| match v.parse::<u16>() {
+            Ok(0) | Err(_) => Err("must be between 1 and 65535"),
+            Ok(port) => Ok(port),
+        })?;
+        let data_dir = parse_or(lookup(&DATA_DIR), PathBuf::from(if on_fly { "/data" } else { "data" }), |v| Ok(PathBuf::from(v)))?;
+        let static_root = parse_or(lookup(&STATIC_ROOT), PathBuf::from("."), |v| {
+            let path = PathBuf::from(v);
+            if path.is_dir() { Ok(path) } else { Err("not a directory") }
+        })?;
+        let dev_mode = parse_or(lookup(&DEV_MODE), false, parse_bool)?;
+        let max_connections = parse_or(lookup(&MAX_CONNECTIONS), 16, |v| match v.parse::<usize>() {
+            Ok(n) if (1..=MAX_CONNECTIONS_LIMIT).contains(&n) => Ok(n),
+            _ => Err("must be between 1 and 1024"),
+        })?;
+        let session_ttl = parse_or(lookup(&SESSION_TTL), Duration::from_secs(30 * 60), |v| match v.parse::<u64>() {
+            Ok(secs) if secs > 0 => Ok(Duration::from_secs(secs)),
+            _ => Err("must be a positive number of seconds"),
+        })?;
+        let log_abandoned = parse_or(lookup(&LOG_ABANDONED), true, parse_bool)?;
+        let storage = parse_or(lookup(&STORAGE), Storage::Csv, |v| match v.to_ascii_lowercase().as_str() {
+            "csv" => Ok(Storage::Csv),
+            "db" => Ok(Storage::Db),
+            _ => Err("must be csv or db"),
+        })?;
+
+        let config = Config { host, port, data_dir, static_root, dev_mode, max_connections, session_ttl, log_abandoned, storage };
+        Ok(if print_config {
+            Command::PrintConfig(config)
+        } else if import_csv {
+            Command::ImportCsv(config)
+        } else {
+            Command::Serve(config)
+        })
+    }
+}
+
+/// Parses a looked-up value, or falls back to `default` if the setting is unset
+fn parse_or<T>(
+    raw: Option<(String, String)>,
+    default: T,
+    parse: impl Fn(&str) -> Result<T, &'static str>,
+) -> Result<T, ConfigError> {
+    match raw {
+        None => Ok(default),
+        Some((source, value)) => parse(&value).map_err(|reason| ConfigError::Invalid { source, value, reason }),
+    }
+}
+
+fn parse_bool(value: &str) -> Result<bool, &'static str> {
+    match value.to_ascii_lowercase().as_str() {
+        "1" | "true" | "yes" | "on" => Ok(true),
+        "0" | "false" | "no" | "off" => Ok(false),
+        _ => Err("must be true or false"),
+    }
+}
+
+impl Config {
+    pub fn bind_addr(&self) -> SocketAddr {
+        SocketAddr::new(self.host, self.port)
+    }
+
+    pub fn csv_path(&self) -> PathBuf {
+        self.data_dir.join("data.csv")
+    }
+
+    pub fn response_db_path(&self) -> PathBuf {
+        self.data_dir.join("responses.db")
+    }
+
+    pub fn archive_dir(&self) -> PathBuf {
+        self.data_dir.join("archive")
+    }
+
+    pub fn session_journal_path(&self) -> PathBuf {
+        self.data_dir.join("sessions.journal")
+    }
+}
+
+impl fmt::Display for Config {
+    /// One `key = value` line per setting, as printed by `--print-config`
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "host = {:?}", self.host.to_string())?;
+        writeln!(f, "port = {}", self.port)?;
+        writeln!(f, "data_dir = {:?}", self.data_dir.display().to_string())?;
+        writeln!(f, "static_root = {:?}", self.static_root.display().to_string())?;
+        writeln!(f, "dev_mode = {}", self.dev_mode)?;
+        writeln!(f, "max_connections = {}", self.max_connections)?;
+        writeln!(f, "session_ttl_secs = {}", self.session_ttl.as_secs())?;
+        writeln!(f, "log_abandoned_sessions = {}", self.log_abandoned)?;
+        writeln!(f, "storage = {:?}", self.storage.as_str())
+    }
+}
+
+/// Text for `--help`
+pub fn usage() -> String {
+    let mut text = String::from("Usage: hpom_minigame [--print-config | --import-csv] [--<setting> <value>]...\n\n\
+         --import-csv copies data.csv into responses.db and exits.\n\nSettings (flag / environment variable):\n");
+    for setting in SETTINGS {
+        text.push_str(&format!("  --{:<24} {:<24} {}\n", setting.flag, setting.env, setting.help));
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str], env: &[(&str, &str)]) -> Result<Command, ConfigError> {
+        let env: Vec<(String, String)> = env.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect();
+        Command::parse(args.iter().map(|a| a.to_string()), |name| {
+            env.iter().find(|(k, _)| k == name).map(|(_, v)| v.clone())
+        })
+    }
+
+    fn config(args: &[&str], env: &[(&str, &str)]) -> Config {
+        match parse(args, env).unwrap() {
+            Command::Serve(config) | Command::PrintConfig(config) | Command::ImportCsv(config) => config,
+            Command::Help => panic!("unexpected --help"),
+        }
+    }
+
+    #[test]
+    fn defaults_depend_on_fly() {
+        assert_eq!(config(&[], &[]).bind_addr().to_string(), "127.0.0.1:7878");
+        assert_eq!(config(&[], &[("FLY_APP_NAME", "app")]).bind_addr().to_string(), "0.0.0.0:8080");
+    }
+
+    #[test]
+    fn flags_override_environment() {
+        let config = config(&["--port", "9000", "--session-ttl-secs=60"], &[("PORT", "8080"), ("HOST", "0.0.0.0")]);
+        assert_eq!(config.bind_addr().to_string(), "0.0.0.0:9000");
+        assert_eq!(config.session_ttl, Duration::from_secs(60));
+    }
+
+    #[test]
+    fn bare_boolean_flags_mean_true() {
+        assert!(!config(&["--dev-mode", "off"], &[]).dev_mode);
+        let config = config(&["--dev-mode", "--port", "80", "--log-abandoned-sessions"], &[("LOG_ABANDONED_SESSIONS", "false")]);
+        assert!(config.dev_mode && config.log_abandoned);
+        assert_eq!(config.port, 80);
+    }
+
+    #[test]
+    fn picks_storage_and_import() {
+        assert_eq!(config(&[], &[]).storage, Storage::Csv);
+        assert_eq!(config(&["--storage", "db"], &[("STORAGE", "csv")]).storage, Storage::Db);
+        assert!(matches!(parse(&["--import-csv"], &[]), Ok(Command::ImportCsv(_))));
+    }
+
+    #[test]
+    fn rejects_bad_values_and_flags() {
+        assert!(matches!(parse(&["--port", "0"], &[]), Err(ConfigError::Invalid { .. })));
+        assert!(matches!(parse(&[], &[("MAX_CONNECTIONS", "many")]), Err(ConfigError::Invalid { .. })));
+        assert!(matches!(parse(&[], &[("LOG_ABANDONED_SESSIONS", "maybe")]), Err(ConfigError::Invalid { .. })));
+        assert!(matches!(parse(&["--storage", "sqlite"], &[]), Err(ConfigError::Invalid { .. })));
+        assert_eq!(parse(&["--verbose"], &[]).unwrap_err(), ConfigError::UnknownFlag("--verbose".to_string()));
+        assert_eq!(parse(&["--port"], &[]).unwrap_err(), ConfigError::MissingValue("--port".to_string()));
+    }
+
+    #[test]
+    fn print_config_lists_every_setting() {
+        let Command::PrintConfig(config) = parse(&["--print-config"], &[]).unwrap() else {
+            panic!("expected --print-config");
+        };
+        assert_eq!(config.to_string().lines().count(), SETTINGS.len());
+    }
+}
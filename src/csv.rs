@@ -0,0 +1,199 @@
+use std::fmt;
+use std::io::{self, Write};
+
+/// Writes RFC 4180 records. Fields containing commas, quotes or line breaks
+/// are quoted, with embedded quotes doubled.
+pub struct Writer<W: Write> {
+    inner: W,
+}
+
+impl<W: Write> Writer<W> {
+    pub fn new(inner: W) -> Self {
+        Self { inner }
+    }
+
+    /// Writes one record. The whole record goes out in a single `write_all`
+    /// so concurrent appenders to the same file never interleave.
+    pub fn write_record<S: AsRef<str>>(&mut self, fields: &[S]) -> io::Result<()> {
+        self.inner.write_all(format_record(fields).as_bytes())
+    }
+}
+
+/// Formats one record, including the trailing CRLF
+pub fn format_record<S: AsRef<str>>(fields: &[S]) -> String {
+    let mut record = String::new();
+    for (i, field) in fields.iter().enumerate() {
+        if i > 0 {
+            record.push(',');
+        }
+        let field = field.as_ref();
+        if field.contains([',', '"', '\r', '\n']) {
+            record.push('"');
+            record.push_str(&field.replace('"', "\"\""));
+            record.push('"');
+        } else {
+            record.push_str(field);
+        }
+    }
+    record.push_str("\r\n");
+    record
+}
+
+#[derive(Debug, PartialEq)]
+pub enum CsvError {
+    UnterminatedQuote(usize),  // line the quoted field started on
+    UnexpectedQuote(usize),    // line with a quote in the middle of a field
+}
+
+impl fmt::Display for CsvError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CsvError::UnterminatedQuote(line) => write!(f, "line {}: quoted field is never closed", line),
+            CsvError::UnexpectedQuote(line) => write!(f, "line {}: unexpected quote inside field", line),
+        }
+    }
+}
+
+/// Reads RFC 4180 records from a string, accepting both CRLF and LF line
+/// endings. Yields one `Vec` of fields per record.
+pub struct Reader<'a> {
+    rest: &'a str,
+    line: usize,
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(input: &'a str) -> Self {
+        Self { rest: input, line: 1 }
+    }
+
+    fn read_record(&mut self) -> Result<Vec<String>, CsvError> {
+        let mut fields = Vec::new();
+        let mut field = String::new();
+        let mut chars = self.rest.char_indices().peekable();
+        let mut quoted = false; // inside a quoted field
+        let mut was_quoted = false; // current field was quoted and has been closed
+        let start_line = self.line;
+
+        while let Some((i, c)) = chars.next() {
+            if quoted {
+                match c {
+                    '"' if matches!(chars.peek(), Some((_, '"'))) => {
+                        chars.next();
+                        field.push('"');
+                    }
+                    '"' => {
+                        quoted = false;
+                        was_quoted = true;
+                    }
+                    '\n' => {
+                        self.line += 1;
+                        field.push(c);
+                    }
+                    _ => field.push(c),
+                }
+                continue;
+            }
+            match c {
+                '"' if field.is_empty() && !was_quoted => quoted = true,
+                '"' => return Err(CsvError::UnexpectedQuote(self.line)),
+                ',' => {
+                    fields.push(std::mem::take(&mut field));
+                    was_quoted = false;
+                }
+                '\r' if matches!(chars.peek(), Some((_, '\n'))) => {}
+                '\n' => {
+                    fields.push(field);
+                    self.rest = &self.rest[i + 1..];
+                    self.line += 1;
+                    return Ok(fields);
+                }
+                _ if was_quoted => return Err(CsvError::UnexpectedQuote(self.line)),
+                _ => field.push(c),
+            }
+        }
+        if quoted {
+            return Err(CsvError::UnterminatedQuote(start_line));
+        }
+        fields.push(field);
+        self.rest = "";
+        Ok(fields)
+    }
+}
+
+impl Iterator for Reader<'_> {
+    type Item = Result<Vec<String>, CsvError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.rest.is_empty() {
+            return None;
+        }
+        let record = self.read_record();
+        if record.is_err() {
+            // Stop after the first error rather than resynchronising mid-field
+            self.rest = "";
+        }
+        Some(record)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(records: &[Vec<&str>]) -> Vec<Vec<String>> {
+        let mut out = Vec::new();
+        let mut writer = Writer::new(&mut out);
+        for record in records {
+            writer.write_record(record).unwrap();
+        }
+        let text = String::from_utf8(out).unwrap();
+        Reader::new(&text).collect::<Result<_, _>>().unwrap()
+    }
+
+    #[test]
+    fn plain_fields_are_not_quoted() {
+        assert_eq!(format_record(&["a", "b c", ""]), "a,b c,\r\n");
+    }
+
+    #[test]
+    fn special_characters_are_quoted() {
+        assert_eq!(format_record(&["a,b", "say \"hi\"", "x\ny"]), "\"a,b\",\"say \"\"hi\"\"\",\"x\ny\"\r\n");
+    }
+
+    #[test]
+    fn round_trips_commas_quotes_and_newlines() {
+        let records = vec![
+            vec!["session_id", "doc_string"],
+            vec!["s1", "User Response Summary:\n- Role: Engineer\n"],
+            vec!["s2", "commas, \"quotes\", and\r\nCRLF"],
+            vec!["", ""],
+            vec!["\"", ","],
+        ];
+        let parsed = round_trip(&records);
+        assert_eq!(parsed, records);
+    }
+
+    #[test]
+    fn reads_lf_line_endings() {
+        let parsed: Vec<_> = Reader::new("a,b\n1,\"2\n3\"\n").collect::<Result<_, _>>().unwrap();
+        assert_eq!(parsed, vec![vec!["a", "b"], vec!["1", "2\n3"]]);
+    }
+
+    #[test]
+    fn reads_last_record_without_line_ending() {
+        let parsed: Vec<_> = Reader::new("a,b\r\nc,d").collect::<Result<_, _>>().unwrap();
+        assert_eq!(parsed, vec![vec!["a", "b"], vec!["c", "d"]]);
+    }
+
+    #[test]
+    fn rejects_unterminated_quote() {
+        let result: Result<Vec<_>, _> = Reader::new("a,b\n\"open,c\n").collect();
+        assert_eq!(result, Err(CsvError::UnterminatedQuote(2)));
+    }
+
+    #[test]
+    fn rejects_quote_inside_unquoted_field() {
+        let result: Result<Vec<_>, _> = Reader::new("a\"b,c\n").collect();
+        assert_eq!(result, Err(CsvError::UnexpectedQuote(1)));
+    }
+}
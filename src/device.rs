@@ -0,0 +1,80 @@
+/// A coarse guess at what kind of client answered, from its `User-Agent`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DeviceClass {
+    Desktop,
+    Mobile,
+    Tablet,
+    /// Crawlers, link previews, scripts and headless browsers
+    Bot,
+    #[default]
+    Unknown,
+}
+
+/// Substrings of user agents that aren't people with a browser
+const BOT_MARKERS: [&str; 14] = [
+    "bot", "crawl", "spider", "slurp", "preview", "facebookexternalhit", "headless", "curl", "wget", "python",
+    "go-http-client", "java/", "okhttp", "httpclient",
+];
+
+impl DeviceClass {
+    pub fn classify(user_agent: Option<&str>) -> Self {
+        let Some(agent) = user_agent.map(str::to_ascii_lowercase).filter(|a| !a.trim().is_empty()) else {
+            return DeviceClass::Unknown;
+        };
+        if BOT_MARKERS.iter().any(|marker| agent.contains(marker)) {
+            DeviceClass::Bot
+        } else if agent.contains("ipad") || agent.contains("tablet") || (agent.contains("android") && !agent.contains("mobile")) {
+            DeviceClass::Tablet
+        } else if ["mobi", "iphone", "ipod", "android"].iter().any(|marker| agent.contains(marker)) {
+            DeviceClass::Mobile
+        } else if ["windows", "macintosh", "x11", "linux", "cros"].iter().any(|marker| agent.contains(marker)) {
+            DeviceClass::Desktop
+        } else {
+            DeviceClass::Unknown
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            DeviceClass::Desktop => "desktop",
+            DeviceClass::Mobile => "mobile",
+            DeviceClass::Tablet => "tablet",
+            DeviceClass::Bot => "bot",
+            DeviceClass::Unknown => "unknown",
+        }
+    }
+
+    /// The inverse of `as_str`; anything else is `Unknown`
+    pub fn parse(text: &str) -> Self {
+        [DeviceClass::Desktop, DeviceClass::Mobile, DeviceClass::Tablet, DeviceClass::Bot]
+            .into_iter()
+            .find(|class| class.as_str() == text)
+            .unwrap_or(DeviceClass::Unknown)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn classifies_common_user_agents() {
+        let cases = [
+            ("Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 Chrome/126.0 Safari/537.36", DeviceClass::Desktop),
+            ("Mozilla/5.0 (Macintosh; Intel Mac OS X 14_5) AppleWebKit/605.1.15 Version/17.5 Safari/605.1.15", DeviceClass::Desktop),
+            ("Mozilla/5.0 (iPhone; CPU iPhone OS 17_5 like Mac OS X) AppleWebKit/605.1.15 Mobile/15E148", DeviceClass::Mobile),
+            ("Mozilla/5.0 (Linux; Android 14; Pixel 8) AppleWebKit/537.36 Chrome/126.0 Mobile Safari/537.36", DeviceClass::Mobile),
+            ("Mozilla/5.0 (Linux; Android 13; SM-X700) AppleWebKit/537.36 Chrome/126.0 Safari/537.36", DeviceClass::Tablet),
+            ("Mozilla/5.0 (iPad; CPU OS 17_5 like Mac OS X) AppleWebKit/605.1.15 Mobile/15E148", DeviceClass::Tablet),
+            ("Mozilla/5.0 (compatible; Googlebot/2.1; +http://www.google.com/bot.html)", DeviceClass::Bot),
+            ("Mozilla/5.0 (X11; Linux x86_64) AppleWebKit/537.36 HeadlessChrome/126.0 Safari/537.36", DeviceClass::Bot),
+            ("curl/8.5.0", DeviceClass::Bot),
+        ];
+        for (agent, class) in cases {
+            assert_eq!(DeviceClass::classify(Some(agent)), class, "{}", agent);
+        }
+        assert_eq!(DeviceClass::classify(None), DeviceClass::Unknown);
+        assert_eq!(DeviceClass::classify(Some("SomethingElse/1.0")), DeviceClass::Unknown);
+        assert_eq!(DeviceClass::parse(DeviceClass::Tablet.as_str()), DeviceClass::Tablet);
+    }
+}
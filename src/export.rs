@@ -0,0 +1,285 @@
+//! Filtered, paginated access to the response CSV for `/api/responses`
+//! (JSON) and `/export.csv`.
+//!
+//! Query parameters:
+//! - `role`, `question_type`: keep responses with that answer, given as the
+//!   label (`Engineer`, case-insensitive) or the button ID (`engi`)
+//! - `from`, `to`: keep responses recorded in that range, as `YYYY-MM-DD` or
+//!   `YYYY-MM-DDTHH:MM:SSZ`; a date-only `to` includes the whole day
+//! - `limit`: responses per page
+//! - `cursor`: the `next_cursor` of the previous page
+
+use crate::csv::{self, CsvError};
+use crate::http::url_decode;
+use crate::labels::LabelRegistry;
+use crate::timestamp;
+use std::fmt::{self, Write};
+use std::time::{Duration, SystemTime};
+
+const RECORDED_AT_COLUMN: &str = "recorded_at";
+
+/// Page size for `/api/responses` when no `limit` is given
+pub const DEFAULT_JSON_LIMIT: usize = 100;
+
+const MAX_LIMIT: usize = 10_000;
+
+#[derive(Debug, PartialEq)]
+pub enum QueryError {
+    UnknownParameter(String),
+    Invalid { name: &'static str, value: String, reason: &'static str },
+}
+
+impl fmt::Display for QueryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            QueryError::UnknownParameter(name) => write!(f, "unknown parameter {}", name),
+            QueryError::Invalid { name, value, reason } => write!(f, "invalid {} {:?}: {}", name, value, reason),
+        }
+    }
+}
+
+/// Which responses to return and where to start
+#[derive(Debug, Default)]
+pub struct Query {
+    /// (CSV column, accepted answer label) pairs that must all match
+    answers: Vec<(&'static str, String)>,
+    from: Option<SystemTime>,
+    /// Exclusive upper bound
+    to: Option<SystemTime>,
+    /// Index of the first data row to look at
+    cursor: usize,
+    limit: Option<usize>,
+}
+
+impl Query {
+    /// Parses a raw query string. `default_limit` applies when there's no
+    /// `limit` parameter; `None` means every matching response.
+    pub fn parse(query: Option<&str>, labels: &LabelRegistry, default_limit: Option<usize>) -> Result<Self, QueryError> {
+        let mut parsed = Query { limit: default_limit, ..Query::default() };
+        let params = query.unwrap_or("").split('&').filter(|p| !p.is_empty());
+        for param in params {
+            let (name, raw) = param.split_once('=').unwrap_or((param, ""));
+            let value = url_decode(raw);
+            let invalid = |name, reason| QueryError::Invalid { name, value: value.clone(), reason };
+            match name {
+                "role" | "question_type" => {
+                    let question = if name == "role" { "role" } else { "question_type" };
+                    let label = labels
+                        .answers_to(question)
+                        .find(|a| a.button == value || a.label.eq_ignore_ascii_case(&value))
+                        .ok_or_else(|| invalid(question, "not one of the answers"))?
+                        .label;
+                    parsed.answers.push((question, label.to_string()));
+                }
+                "from" => parsed.from = Some(timestamp::parse_rfc3339(&value).ok_or_else(|| invalid("from", "not a date"))?),
+                "to" => {
+                    let to = timestamp::parse_rfc3339(&value).ok_or_else(|| invalid("to", "not a date"))?;
+                    // A bare date means the end of that day; a full timestamp is inclusive too
+                    let inclusive = if value.contains('T') { Duration::from_secs(1) } else { Duration::from_secs(86_400) };
+                    parsed.to = Some(to.checked_add(inclusive).ok_or_else(|| invalid("to", "out of range"))?);
+                }
+                "cursor" => parsed.cursor = value.parse().map_err(|_| invalid("cursor", "not a cursor from a previous page"))?,
+                "limit" => {
+                    parsed.limit = match value.parse() {
+                        Ok(limit) if (1..=MAX_LIMIT).contains(&limit) => Some(limit),
+                        _ => return Err(invalid("limit", "must be between 1 and 10000")),
+                    }
+                }
+                _ => return Err(QueryError::UnknownParameter(name.to_string())),
+            }
+        }
+        Ok(parsed)
+    }
+
+    /// Every response, in one page
+    pub fn all() -> Self {
+        Self::default()
+    }
+
+    /// The (column, answer label) pairs a response must match
+    pub fn answers(&self) -> &[(&'static str, String)] {
+        &self.answers
+    }
+
+    /// The `recorded_at` range, from inclusive and to exclusive
+    pub fn range(&self) -> (Option<SystemTime>, Option<SystemTime>) {
+        (self.from, self.to)
+    }
+
+    pub fn cursor(&self) -> usize {
+        self.cursor
+    }
+
+    pub fn limit(&self) -> Option<usize> {
+        self.limit
+    }
+
+    /// The same query starting at `cursor`, as a query string
+    pub fn with_cursor(raw_query: Option<&str>, cursor: usize) -> String {
+        let mut params: Vec<&str> = raw_query
+            .unwrap_or("")
+            .split('&')
+            .filter(|p| !p.is_empty() && !p.starts_with("cursor="))
+            .collect();
+        let cursor = format!("cursor={}", cursor);
+        params.push(&cursor);
+        params.join("&")
+    }
+
+    /// Whether a response matches the filters; `column` finds a column's
+    /// index in `fields` by name
+    pub fn matches(&self, column: impl Fn(&str) -> Option<usize>, fields: &[String]) -> bool {
+        let field = |name: &str| column(name).and_then(|i| fields.get(i)).map_or("", |f| f.as_str());
+        if !self.answers.iter().all(|(question, label)| field(question) == label) {
+            return false;
+        }
+        if self.from.is_none() && self.to.is_none() {
+            return true;
+        }
+        // Rows without a readable timestamp can't be placed in a range
+        let Some(recorded) = timestamp::parse_rfc3339(field(RECORDED_AT_COLUMN)) else {
+            return false;
+        };
+        self.from.is_none_or(|from| recorded >= from) && self.to.is_none_or(|to| recorded < to)
+    }
+}
+
+/// One page of matching responses
+#[derive(Debug)]
+pub struct Page {
+    pub columns: Vec<String>,
+    pub rows: Vec<Vec<String>>,
+    /// Where the next page starts, if there are more matches
+    pub next_cursor: Option<usize>,
+}
+
+/// Selects the page of `csv` that `query` asks for
+pub fn select(csv: &str, query: &Query) -> Result<Page, CsvError> {
+    let mut records = csv::Reader::new(csv);
+    let columns = records.next().transpose()?.unwrap_or_default();
+    let column = |name: &str| columns.iter().position(|c| c == name);
+    let mut rows = Vec::new();
+    let mut next_cursor = None;
+    for (index, record) in records.enumerate().skip(query.cursor) {
+        let fields = record?;
+        if !query.matches(column, &fields) {
+            continue;
+        }
+        if query.limit.is_some_and(|limit| rows.len() == limit) {
+            next_cursor = Some(index);
+            break;
+        }
+        rows.push(fields);
+    }
+    Ok(Page { columns, rows, next_cursor })
+}
+
+impl Page {
+    /// `{"responses": [{column: value, ...}], "next_cursor": "..." | null}`
+    pub fn to_json(&self) -> String {
+        let mut json = String::from("{\"responses\":[");
+        for (i, row) in self.rows.iter().enumerate() {
+            json.push_str(if i == 0 { "{" } else { ",{" });
+            for (j, column) in self.columns.iter().enumerate() {
+                let value = row.get(j).map_or("", |v| v.as_str());
+                write!(json, "{}{}:{}", if j == 0 { "" } else { "," }, json_string(column), json_string(value)).unwrap();
+            }
+            json.push('}');
+        }
+        json.push_str("],\"next_cursor\":");
+        match self.next_cursor {
+            // A string so clients treat it as opaque
+            Some(cursor) => json.push_str(&json_string(&cursor.to_string())),
+            None => json.push_str("null"),
+        }
+        json.push('}');
+        json
+    }
+
+    /// The header and rows as CSV
+    pub fn to_csv(&self) -> String {
+        let mut out = csv::format_record(&self.columns);
+        for row in &self.rows {
+            out.push_str(&csv::format_record(row));
+        }
+        out
+    }
+}
+
+/// `s` as a quoted JSON string
+pub fn json_string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => write!(out, "\\u{:04x}", c as u32).unwrap(),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CSV: &str = "session_id,status,recorded_at,role,question_type\n\
+                       a,completed,2026-01-01T10:00:00Z,Engineer,Multiple Choice\n\
+                       b,completed,2026-01-02T10:00:00Z,UX Designer,True/False\n\
+                       c,abandoned,2026-01-03T10:00:00Z,Engineer,True/False\n\
+                       d,completed,2026-01-04T10:00:00Z,Engineer,Multiple Choice\n";
+
+    fn ids(query: &str, default_limit: Option<usize>) -> (Vec<String>, Option<usize>) {
+        let query = Query::parse(Some(query), &LabelRegistry::builtin(), default_limit).unwrap();
+        let page = select(CSV, &query).unwrap();
+        (page.rows.into_iter().map(|row| row[0].clone()).collect(), page.next_cursor)
+    }
+
+    #[test]
+    fn filters_by_answer_and_date() {
+        assert_eq!(ids("role=engi", None).0, ["a", "c", "d"]);
+        assert_eq!(ids("role=Engineer&question_type=true%2Ffalse", None).0, ["c"]);
+        assert_eq!(ids("from=2026-01-02&to=2026-01-03", None).0, ["b", "c"]);
+        assert_eq!(ids("to=2026-01-02T10:00:00Z", None).0, ["a", "b"]);
+    }
+
+    #[test]
+    fn pages_with_cursors() {
+        assert_eq!(ids("role=engi&limit=2", None), (vec!["a".to_string(), "c".to_string()], Some(3)));
+        assert_eq!(ids("role=engi&limit=2&cursor=3", None), (vec!["d".to_string()], None));
+        assert_eq!(ids("", Some(4)).1, None);
+        assert_eq!(Query::with_cursor(Some("role=engi&cursor=1&limit=2"), 3), "role=engi&limit=2&cursor=3");
+    }
+
+    #[test]
+    fn rejects_bad_parameters() {
+        let labels = LabelRegistry::builtin();
+        for query in [
+            "role=intern",
+            "from=soon",
+            "limit=0",
+            "cursor=abc",
+            "sort=role",
+            "to=292277026596-12-04",
+            "from=99999999999999999-01-01",
+        ] {
+            assert!(Query::parse(Some(query), &labels, None).is_err(), "{}", query);
+        }
+    }
+
+    #[test]
+    fn writes_json() {
+        let page = Page {
+            columns: vec!["id".to_string(), "doc".to_string()],
+            rows: vec![vec!["a".to_string(), "say \"hi\"\n".to_string()]],
+            next_cursor: Some(7),
+        };
+        assert_eq!(page.to_json(), r#"{"responses":[{"id":"a","doc":"say \"hi\"\n"}],"next_cursor":"7"}"#);
+    }
+}
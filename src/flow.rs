@@ -0,0 +1,346 @@
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::fmt;
+
+/// The survey graph: which buttons each page accepts and where they lead.
+/// Loaded from `flow.toml` at startup and shared read-only by every session.
+#[derive(Debug, Clone)]
+pub struct SurveyFlow {
+    start: usize,
+    terminal: usize,
+    pages: BTreeMap<usize, Page>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Page {
+    requires: Vec<String>,
+    transitions: Vec<(String, usize)>, // button, next page
+}
+
+#[derive(Debug, PartialEq)]
+pub enum FlowError {
+    Parse(usize, String), // line number, message
+    MissingPage(usize),
+    DuplicatePage(usize),
+    DuplicateButton(usize, String),
+    DanglingTransition(usize, String, usize), // from, button, to
+    UnreachablePage(usize),
+    DeadEnd(usize),
+    UnsatisfiableRequirement(usize, String), // page, button
+}
+
+impl fmt::Display for FlowError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FlowError::Parse(line, msg) => write!(f, "line {}: {}", line, msg),
+            FlowError::MissingPage(page) => write!(f, "page {} is referenced but not defined", page),
+            FlowError::DuplicatePage(page) => write!(f, "page {} is defined more than once", page),
+            FlowError::DuplicateButton(page, button) => {
+                write!(f, "button '{}' has more than one transition on page {}", button, page)
+            }
+            FlowError::DanglingTransition(from, button, to) => {
+                write!(f, "button '{}' on page {} leads to undefined page {}", button, from, to)
+            }
+            FlowError::UnreachablePage(page) => write!(f, "page {} cannot be reached from the start page", page),
+            FlowError::DeadEnd(page) => write!(f, "page {} has no path to the terminal page", page),
+            FlowError::UnsatisfiableRequirement(page, button) => {
+                write!(f, "page {} requires button '{}', but no page leading to it has that button", page, button)
+            }
+        }
+    }
+}
+
+impl Page {
+    /// Buttons that must already have been pressed before this page accepts input
+    pub fn requires(&self) -> &[String] {
+        &self.requires
+    }
+
+    /// Buttons accepted on this page, in declaration order
+    pub fn buttons(&self) -> Vec<String> {
+        self.transitions.iter().map(|(button, _)| button.clone()).collect()
+    }
+
+    /// The page a button leads to, if the button is allowed here
+    pub fn next_page(&self, button: &str) -> Option<usize> {
+        self.transitions.iter().find(|(b, _)| b == button).map(|(_, to)| *to)
+    }
+}
+
+impl SurveyFlow {
+    /// Parses a flow definition and rejects graphs that users could get stuck in
+    pub fn parse(text: &str) -> Result<Self, FlowError> {
+        let doc = parse_toml_subset(text)?;
+        let start = doc.root.int("start")?;
+        let terminal = doc.root.int("terminal")?;
+
+        let mut pages = BTreeMap::new();
+        for table in doc.tables_named("page") {
+            let id = table.int("id")?;
+            let page = Page { requires: table.strings("requires")?, transitions: Vec::new() };
+            if pages.insert(id, page).is_some() {
+                return Err(FlowError::DuplicatePage(id));
+            }
+        }
+
+        for table in doc.tables_named("transition") {
+            let from = table.int("from")?;
+            let to = table.int("to")?;
+            let buttons = table.strings("buttons")?;
+            if buttons.is_empty() {
+                return Err(FlowError::Parse(table.line, "transition needs at least one button".to_string()));
+            }
+            let page = pages.get_mut(&from).ok_or(FlowError::MissingPage(from))?;
+            for button in buttons {
+                if page.next_page(&button).is_some() {
+                    return Err(FlowError::DuplicateButton(from, button));
+                }
+                page.transitions.push((button, to));
+            }
+        }
+
+        let flow = SurveyFlow { start, terminal, pages };
+        flow.validate()?;
+        Ok(flow)
+    }
+
+    fn validate(&self) -> Result<(), FlowError> {
+        for page in [self.start, self.terminal] {
+            if !self.pages.contains_key(&page) {
+                return Err(FlowError::MissingPage(page));
+            }
+        }
+        for (&from, page) in &self.pages {
+            for (button, to) in &page.transitions {
+                if !self.pages.contains_key(to) {
+                    return Err(FlowError::DanglingTransition(from, button.clone(), *to));
+                }
+            }
+        }
+
+        // Every page must be reachable from the start page...
+        let reachable = self.walk(self.start, |page| {
+            self.pages[&page].transitions.iter().map(|(_, to)| *to).collect()
+        });
+        if let Some(&page) = self.pages.keys().find(|page| !reachable.contains(page)) {
+            return Err(FlowError::UnreachablePage(page));
+        }
+
+        // ...and must be able to reach the terminal page
+        let finishes = self.walk(self.terminal, |page| {
+            self.pages
+                .iter()
+                .filter(|(_, p)| p.transitions.iter().any(|(_, to)| *to == page))
+                .map(|(&from, _)| from)
+                .collect()
+        });
+        if let Some(&page) = self.pages.keys().find(|page| !finishes.contains(page)) {
+            return Err(FlowError::DeadEnd(page));
+        }
+
+        // A required button must be on some page that can lead here, or the
+        // page could never accept input
+        for (&id, page) in &self.pages {
+            for required in &page.requires {
+                let satisfiable = self.pages.values().any(|p| {
+                    p.next_page(required).is_some_and(|to| {
+                        self.walk(to, |page| self.pages[&page].transitions.iter().map(|(_, to)| *to).collect()).contains(&id)
+                    })
+                });
+                if !satisfiable {
+                    return Err(FlowError::UnsatisfiableRequirement(id, required.clone()));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Breadth-first search from `from`, following `edges`
+    fn walk(&self, from: usize, edges: impl Fn(usize) -> Vec<usize>) -> BTreeSet<usize> {
+        let mut seen = BTreeSet::from([from]);
+        let mut queue = VecDeque::from([from]);
+        while let Some(page) = queue.pop_front() {
+            for next in edges(page) {
+                if seen.insert(next) {
+                    queue.push_back(next);
+                }
+            }
+        }
+        seen
+    }
+
+    /// Page every new session starts on
+    pub fn start_page(&self) -> usize {
+        self.start
+    }
+
+    /// Page that ends the survey
+    pub fn terminal_page(&self) -> usize {
+        self.terminal
+    }
+
+    /// Look up a page definition
+    pub fn page(&self, page: usize) -> Option<&Page> {
+        self.pages.get(&page)
+    }
+}
+
+// Just enough TOML for flow files: comments, `key = value` pairs and
+// `[[table]]` array headers, where values are integers, strings or arrays
+// of strings.
+
+enum Value {
+    Int(usize),
+    Str(String),
+    Array(Vec<String>),
+}
+
+struct Table {
+    name: String,
+    line: usize,
+    entries: Vec<(String, Value)>,
+}
+
+struct Document {
+    root: Table,
+    tables: Vec<Table>,
+}
+
+impl Document {
+    fn tables_named<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a Table> + 'a {
+        self.tables.iter().filter(move |t| t.name == name)
+    }
+}
+
+impl Table {
+    fn get(&self, key: &str) -> Option<&Value> {
+        self.entries.iter().find(|(k, _)| k == key).map(|(_, v)| v)
+    }
+
+    fn int(&self, key: &str) -> Result<usize, FlowError> {
+        match self.get(key) {
+            Some(Value::Int(n)) => Ok(*n),
+            Some(_) => Err(FlowError::Parse(self.line, format!("'{}' must be an integer", key))),
+            None => Err(FlowError::Parse(self.line, format!("missing '{}'", key))),
+        }
+    }
+
+    fn strings(&self, key: &str) -> Result<Vec<String>, FlowError> {
+        match self.get(key) {
+            Some(Value::Array(items)) => Ok(items.clone()),
+            Some(Value::Str(s)) => Ok(vec![s.clone()]),
+            Some(Value::Int(_)) => Err(FlowError::Parse(self.line, format!("'{}' must be a list of strings", key))),
+            None => Ok(Vec::new()),
+        }
+    }
+}
+
+fn parse_toml_subset(text: &str) -> Result<Document, FlowError> {
+    let mut root = Table { name: String::new(), line: 1, entries: Vec::new() };
+    let mut tables: Vec<Table> = Vec::new();
+
+    for (index, raw) in text.lines().enumerate() {
+        let line_no = index + 1;
+        let line = strip_comment(raw).trim();
+        if line.is_empty() {
+            continue;
+        }
+        if let Some(name) = line.strip_prefix("[[").and_then(|l| l.strip_suffix("]]")) {
+            tables.push(Table { name: name.trim().to_string(), line: line_no, entries: Vec::new() });
+            continue;
+        }
+        let (key, value) = line
+            .split_once('=')
+            .ok_or_else(|| FlowError::Parse(line_no, format!("expected 'key = value', got '{}'", line)))?;
+        let value = parse_value(value.trim()).map_err(|msg| FlowError::Parse(line_no, msg))?;
+        let table = tables.last_mut().unwrap_or(&mut root);
+        table.entries.push((key.trim().to_string(), value));
+    }
+    Ok(Document { root, tables })
+}
+
+fn strip_comment(line: &str) -> &str {
+    let mut in_string = false;
+    for (i, c) in line.char_indices() {
+        match c {
+            '"' => in_string = !in_string,
+            '#' if !in_string => return &line[..i],
+            _ => {}
+        }
+    }
+    line
+}
+
+fn parse_value(value: &str) -> Result<Value, String> {
+    if let Some(inner) = value.strip_prefix('[').and_then(|v| v.strip_suffix(']')) {
+        let items = inner
+            .split(',')
+            .map(str::trim)
+            .filter(|item| !item.is_empty())
+            .map(parse_string)
+            .collect::<Result<Vec<_>, _>>()?;
+        return Ok(Value::Array(items));
+    }
+    if value.starts_with('"') {
+        return parse_string(value).map(Value::Str);
+    }
+    value.parse().map(Value::Int).map_err(|_| format!("unsupported value '{}'", value))
+}
+
+fn parse_string(value: &str) -> Result<String, String> {
+    value
+        .strip_prefix('"')
+        .and_then(|v| v.strip_suffix('"'))
+        .filter(|v| !v.contains('"'))
+        .map(str::to_string)
+        .ok_or_else(|| format!("expected a quoted string, got '{}'", value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A three-page flow, with `extra` appended
+    fn parse(extra: &str) -> Result<SurveyFlow, FlowError> {
+        let text = format!(
+            "start = 1\nterminal = 3\n\
+             [[page]]\nid = 1\n[[page]]\nid = 2\nrequires = [\"go\"]\n[[page]]\nid = 3\n\
+             [[transition]]\nfrom = 1\nto = 2\nbuttons = [\"go\"]\n\
+             [[transition]]\nfrom = 2\nto = 3\nbuttons = [\"done\"]\n{}",
+            extra
+        );
+        SurveyFlow::parse(&text)
+    }
+
+    #[test]
+    fn accepts_the_shipped_flow() {
+        let flow = SurveyFlow::parse(include_str!("../flow.toml")).unwrap();
+        assert_eq!(flow.page(flow.start_page()).unwrap().next_page("start"), Some(2));
+        assert!(parse("").is_ok());
+    }
+
+    #[test]
+    fn rejects_broken_graphs() {
+        assert_eq!(parse("[[page]]\nid = 4\n[[transition]]\nfrom = 4\nto = 3\nbuttons = [\"x\"]\n").unwrap_err(), FlowError::UnreachablePage(4));
+        assert_eq!(
+            parse("[[transition]]\nfrom = 1\nto = 7\nbuttons = [\"x\"]\n").unwrap_err(),
+            FlowError::DanglingTransition(1, "x".to_string(), 7)
+        );
+        assert_eq!(parse("[[page]]\nid = 4\n[[transition]]\nfrom = 1\nto = 4\nbuttons = [\"x\"]\n").unwrap_err(), FlowError::DeadEnd(4));
+        assert_eq!(parse("[[page]]\nid = 2\n").unwrap_err(), FlowError::DuplicatePage(2));
+    }
+
+    #[test]
+    fn rejects_requirements_no_earlier_page_offers() {
+        let typo = SurveyFlow::parse(
+            "start = 1\nterminal = 2\n[[page]]\nid = 1\n[[page]]\nid = 2\nrequires = [\"og\"]\n\
+             [[transition]]\nfrom = 1\nto = 2\nbuttons = [\"go\"]\n",
+        );
+        assert_eq!(typo.unwrap_err(), FlowError::UnsatisfiableRequirement(2, "og".to_string()));
+        // The button exists, but only after the page that requires it
+        let later = SurveyFlow::parse(
+            "start = 1\nterminal = 3\n[[page]]\nid = 1\nrequires = [\"done\"]\n[[page]]\nid = 2\n[[page]]\nid = 3\n\
+             [[transition]]\nfrom = 1\nto = 2\nbuttons = [\"go\"]\n[[transition]]\nfrom = 2\nto = 3\nbuttons = [\"done\"]\n",
+        );
+        assert_eq!(later.unwrap_err(), FlowError::UnsatisfiableRequirement(1, "done".to_string()));
+    }
+}
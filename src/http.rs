@@ -0,0 +1,371 @@
+use std::io::{self, BufRead, Read};
+
+/// Longest request line we accept, in bytes
+pub const MAX_REQUEST_LINE: usize = 8 * 1024;
+/// Total size of all header lines, including their CRLFs, in bytes
+pub const MAX_HEADER_BYTES: usize = 16 * 1024;
+pub const MAX_HEADERS: usize = 100;
+pub const MAX_BODY_BYTES: usize = 16 * 1024;
+
+/// A parsed HTTP/1.x request
+#[derive(Debug)]
+pub struct Request {
+    pub method: String,
+    pub target: String,
+    pub version: String,
+    headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+/// Why a request could not be read. Every variant except `ConnectionClosed`
+/// maps to an error response for the client.
+#[derive(Debug)]
+pub enum ParseError {
+    /// The client closed the connection before sending a whole request
+    ConnectionClosed,
+    BadRequest(&'static str),
+    UriTooLong,
+    HeadersTooLarge,
+    PayloadTooLarge,
+    NotImplemented(&'static str),
+    Io(io::Error),
+}
+
+impl ParseError {
+    /// The response to send back, or `None` if there is nobody to answer
+    pub fn response(&self) -> Option<Response> {
+        let response = match self {
+            ParseError::ConnectionClosed | ParseError::Io(_) => return None,
+            ParseError::BadRequest(reason) => Response::text("400 BAD REQUEST", reason),
+            ParseError::UriTooLong => Response::text("414 URI TOO LONG", "Request line too long."),
+            ParseError::HeadersTooLarge => Response::text("431 REQUEST HEADER FIELDS TOO LARGE", "Request headers too large."),
+            ParseError::PayloadTooLarge => Response::text("413 PAYLOAD TOO LARGE", "Request body too large."),
+            ParseError::NotImplemented(reason) => Response::text("501 NOT IMPLEMENTED", reason),
+        };
+        Some(response.with_header("Connection", "close"))
+    }
+}
+
+impl std::fmt::Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ParseError::ConnectionClosed => write!(f, "connection closed before request"),
+            ParseError::BadRequest(reason) => write!(f, "bad request: {}", reason),
+            ParseError::UriTooLong => write!(f, "request line too long"),
+            ParseError::HeadersTooLarge => write!(f, "headers too large"),
+            ParseError::PayloadTooLarge => write!(f, "body too large"),
+            ParseError::NotImplemented(reason) => write!(f, "not implemented: {}", reason),
+            ParseError::Io(e) => write!(f, "I/O error: {}", e),
+        }
+    }
+}
+
+impl Request {
+    /// Reads one request (request line, headers and `Content-Length` body)
+    pub fn read_from<R: BufRead>(reader: &mut R) -> Result<Self, ParseError> {
+        let request_line = match read_line(reader, MAX_REQUEST_LINE)? {
+            Line::Complete(line) => line,
+            Line::Eof => return Err(ParseError::ConnectionClosed),
+            Line::TooLong => return Err(ParseError::UriTooLong),
+        };
+        let mut parts = request_line.split(' ');
+        let (Some(method), Some(target), Some(version), None) = (parts.next(), parts.next(), parts.next(), parts.next())
+        else {
+            return Err(ParseError::BadRequest("Malformed request line."));
+        };
+        if method.is_empty() || !method.bytes().all(|b| b.is_ascii_uppercase()) {
+            return Err(ParseError::BadRequest("Malformed method."));
+        }
+        if !target.starts_with('/') {
+            return Err(ParseError::BadRequest("Malformed request target."));
+        }
+        if version != "HTTP/1.1" && version != "HTTP/1.0" {
+            return Err(ParseError::BadRequest("Unsupported HTTP version."));
+        }
+
+        let mut headers = Vec::new();
+        let mut header_bytes = 0;
+        loop {
+            // Leave room for this line's CRLF. Once the budget is spent only the
+            // blank line ending the headers fits.
+            let limit = MAX_HEADER_BYTES.saturating_sub(header_bytes).saturating_sub(2);
+            let line = match read_line(reader, limit)? {
+                Line::Complete(line) => line,
+                Line::TooLong => return Err(ParseError::HeadersTooLarge),
+                Line::Eof => return Err(ParseError::ConnectionClosed),
+            };
+            if line.is_empty() {
+                break;
+            }
+            header_bytes += line.len() + 2;
+            if headers.len() == MAX_HEADERS {
+                return Err(ParseError::HeadersTooLarge);
+            }
+            let (name, value) = line.split_once(':').ok_or(ParseError::BadRequest("Malformed header."))?;
+            if name.is_empty() || name.contains([' ', '\t']) {
+                return Err(ParseError::BadRequest("Malformed header name."));
+            }
+            headers.push((name.to_string(), value.trim().to_string()));
+        }
+
+        let mut request = Request {
+            method: method.to_string(),
+            target: target.to_string(),
+            version: version.to_string(),
+            headers,
+            body: Vec::new(),
+        };
+        if request.header("transfer-encoding").is_some() {
+            return Err(ParseError::NotImplemented("Transfer-Encoding is not supported."));
+        }
+        let content_length = match request.header("content-length") {
+            Some(value) => value.parse::<usize>().map_err(|_| ParseError::BadRequest("Invalid Content-Length."))?,
+            None => 0,
+        };
+        if content_length > MAX_BODY_BYTES {
+            return Err(ParseError::PayloadTooLarge);
+        }
+        request.body = vec![0; content_length];
+        reader.read_exact(&mut request.body).map_err(|e| match e.kind() {
+            io::ErrorKind::UnexpectedEof => ParseError::BadRequest("Body shorter than Content-Length."),
+            _ => ParseError::Io(e),
+        })?;
+        Ok(request)
+    }
+
+    /// First header with this name, compared case-insensitively
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// The target without its query string
+    pub fn path(&self) -> &str {
+        self.target.split_once('?').map_or(&self.target, |(path, _)| path)
+    }
+
+    /// Whether the client wants the connection kept open after this
+    /// request: the default for HTTP/1.1, opt-in for HTTP/1.0
+    pub fn wants_keep_alive(&self) -> bool {
+        let has_token = |token: &str| {
+            self.header("connection")
+                .is_some_and(|value| value.split(',').any(|t| t.trim().eq_ignore_ascii_case(token)))
+        };
+        if self.version == "HTTP/1.0" {
+            has_token("keep-alive")
+        } else {
+            !has_token("close")
+        }
+    }
+
+    /// The query string, if the target has one
+    pub fn query(&self) -> Option<&str> {
+        self.target.split_once('?').map(|(_, query)| query)
+    }
+}
+
+enum Line {
+    Complete(String),
+    TooLong,
+    Eof,
+}
+
+/// Reads a CRLF- (or bare LF-) terminated line of at most `limit` bytes
+fn read_line<R: BufRead>(reader: &mut R, limit: usize) -> Result<Line, ParseError> {
+    let mut buf = Vec::new();
+    reader
+        .take(limit as u64 + 2)
+        .read_until(b'\n', &mut buf)
+        .map_err(ParseError::Io)?;
+    if buf.is_empty() {
+        return Ok(Line::Eof);
+    }
+    if buf.last() != Some(&b'\n') {
+        return Ok(if buf.len() > limit { Line::TooLong } else { Line::Eof });
+    }
+    buf.pop();
+    if buf.last() == Some(&b'\r') {
+        buf.pop();
+    }
+    if buf.len() > limit {
+        return Ok(Line::TooLong);
+    }
+    String::from_utf8(buf)
+        .map(Line::Complete)
+        .map_err(|_| ParseError::BadRequest("Request is not valid UTF-8."))
+}
+
+/// An HTTP response waiting to be written to the client
+#[derive(Debug)]
+pub struct Response {
+    pub status: &'static str,
+    pub content_type: String,
+    pub body: Vec<u8>,
+    pub headers: Vec<(String, String)>,
+}
+
+impl Response {
+    pub fn new(status: &'static str, content_type: &str, body: Vec<u8>) -> Self {
+        Self {
+            status,
+            content_type: content_type.to_string(),
+            body,
+            headers: Vec::new(),
+        }
+    }
+
+    pub fn html(status: &'static str, html: String) -> Self {
+        Self::new(status, "text/html", html.into_bytes())
+    }
+
+    pub fn text(status: &'static str, text: &str) -> Self {
+        Self::new(status, "text/plain", text.as_bytes().to_vec())
+    }
+
+    pub fn with_header(mut self, name: &str, value: impl Into<String>) -> Self {
+        self.headers.push((name.to_string(), value.into()));
+        self
+    }
+
+    /// First header with this name, compared case-insensitively
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Serializes the status line, headers and body
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut head = format!("HTTP/1.1 {}\r\n", self.status);
+        // A 304 describes the cached copy, so it must not claim an empty body
+        if !self.status.starts_with("304") {
+            head.push_str(&format!("Content-Length: {}\r\nContent-Type: {}\r\n", self.body.len(), self.content_type));
+        }
+        for (name, value) in &self.headers {
+            head.push_str(&format!("{}: {}\r\n", name, value));
+        }
+        head.push_str("\r\n");
+        let mut bytes = head.into_bytes();
+        bytes.extend_from_slice(&self.body);
+        bytes
+    }
+}
+
+/// Decodes `application/x-www-form-urlencoded` text: `+` is a space and
+/// `%XX` is a byte. Malformed escapes are kept as-is.
+pub fn url_decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'+' => out.push(b' '),
+            b'%' if i + 2 < bytes.len() => match (hex_value(bytes[i + 1]), hex_value(bytes[i + 2])) {
+                (Some(hi), Some(lo)) => {
+                    out.push(hi << 4 | lo);
+                    i += 2;
+                }
+                _ => out.push(b'%'),
+            },
+            b => out.push(b),
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+/// Decodes `%XX` escapes in a URL path. Unlike `url_decode`, `+` stays a
+/// plus sign. Returns `None` for malformed escapes or invalid UTF-8.
+pub fn percent_decode_path(s: &str) -> Option<String> {
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hi = hex_value(*bytes.get(i + 1)?)?;
+            let lo = hex_value(*bytes.get(i + 2)?)?;
+            out.push(hi << 4 | lo);
+            i += 3;
+        } else {
+            out.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8(out).ok()
+}
+
+fn hex_value(b: u8) -> Option<u8> {
+    (b as char).to_digit(16).map(|d| d as u8)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(raw: &[u8]) -> Result<Request, ParseError> {
+        Request::read_from(&mut &raw[..])
+    }
+
+    #[test]
+    fn parses_request_line_headers_and_body() {
+        let request = parse(b"POST /page2?x=1 HTTP/1.1\r\nContent-Type: text/plain\r\nContent-Length: 5\r\n\r\nhello").unwrap();
+        assert_eq!(request.method, "POST");
+        assert_eq!(request.path(), "/page2");
+        assert_eq!(request.query(), Some("x=1"));
+        assert_eq!(request.header("CONTENT-TYPE"), Some("text/plain"));
+        assert_eq!(request.body, b"hello");
+    }
+
+    #[test]
+    fn empty_connection_has_no_response() {
+        let err = parse(b"").unwrap_err();
+        assert!(matches!(err, ParseError::ConnectionClosed));
+        assert!(err.response().is_none());
+    }
+
+    #[test]
+    fn rejects_malformed_input_with_400() {
+        for raw in [&b"GET /\r\n\r\n"[..], b"GET / HTTP/1.1\r\nno-colon\r\n\r\n", b"GET / HTTP/1.1\r\n\xff: x\r\n\r\n"] {
+            assert_eq!(parse(raw).unwrap_err().response().unwrap().status, "400 BAD REQUEST");
+        }
+    }
+
+    #[test]
+    fn enforces_size_limits() {
+        let long_line = format!("GET /{} HTTP/1.1\r\n\r\n", "a".repeat(MAX_REQUEST_LINE));
+        assert!(matches!(parse(long_line.as_bytes()), Err(ParseError::UriTooLong)));
+
+        let big_header = format!("GET / HTTP/1.1\r\nX: {}\r\n\r\n", "v".repeat(MAX_HEADER_BYTES));
+        assert!(matches!(parse(big_header.as_bytes()), Err(ParseError::HeadersTooLarge)));
+
+        let header_line = |len: usize| format!("X: {}\r\n", "v".repeat(len - "X: \r\n".len()));
+        let at_limit = format!("GET / HTTP/1.1\r\n{}\r\n", header_line(MAX_HEADER_BYTES));
+        assert!(parse(at_limit.as_bytes()).is_ok());
+        let over_limit = format!("GET / HTTP/1.1\r\n{}\r\n", header_line(MAX_HEADER_BYTES + 1));
+        assert!(matches!(parse(over_limit.as_bytes()), Err(ParseError::HeadersTooLarge)));
+        // The CRLF of a line just under the budget tips it over, without underflowing
+        let split = format!("GET / HTTP/1.1\r\n{}{}\r\n", header_line(MAX_HEADER_BYTES - 1), header_line(7));
+        assert!(matches!(parse(split.as_bytes()), Err(ParseError::HeadersTooLarge)));
+        let one_short = format!("GET / HTTP/1.1\r\nX: {}\r\nY: z\r\n\r\n", "v".repeat(MAX_HEADER_BYTES - 1 - "X: ".len()));
+        assert!(matches!(parse(one_short.as_bytes()), Err(ParseError::HeadersTooLarge)));
+
+        let big_body = format!("POST / HTTP/1.1\r\nContent-Length: {}\r\n\r\n", MAX_BODY_BYTES + 1);
+        assert!(matches!(parse(big_body.as_bytes()), Err(ParseError::PayloadTooLarge)));
+    }
+
+    #[test]
+    fn decodes_form_values() {
+        assert_eq!(url_decode("a+b%2Cc%zz"), "a b,c%zz");
+    }
+
+    #[test]
+    fn decodes_paths_strictly() {
+        assert_eq!(percent_decode_path("a+b%20c%2e%2E").as_deref(), Some("a+b c.."));
+        assert_eq!(percent_decode_path("bad%2"), None);
+        assert_eq!(percent_decode_path("bad%zz"), None);
+        assert_eq!(percent_decode_path("%ff"), None);
+    }
+}
@@ -0,0 +1,180 @@
+/// A survey question. `id` doubles as the CSV column name.
+#[derive(Debug, Clone)]
+pub struct Question {
+    pub id: &'static str,
+    pub prompt: &'static str,
+}
+
+/// What pressing a button means: the question it answers and a human label.
+#[derive(Debug, Clone)]
+pub struct Answer {
+    pub button: &'static str,
+    pub question: &'static str,
+    pub label: &'static str,
+}
+
+/// Single source of truth for turning button IDs into readable answers.
+/// The doc string, CSV columns and `/view-data` are all generated from it.
+#[derive(Debug, Clone)]
+pub struct LabelRegistry {
+    questions: Vec<Question>,
+    answers: Vec<Answer>,
+}
+
+const SESSION_COLUMN: &str = "session_id";
+const STATUS_COLUMN: &str = "status";
+const LAST_PAGE_COLUMN: &str = "last_page";
+const RECORDED_AT_COLUMN: &str = "recorded_at";
+const STARTED_AT_COLUMN: &str = "started_at";
+const FINISHED_AT_COLUMN: &str = "finished_at";
+const DEVICE_COLUMN: &str = "device";
+const PAGE_DWELL_COLUMN: &str = "page_dwell_secs";
+const DOC_STRING_COLUMN: &str = "doc_string";
+
+impl LabelRegistry {
+    pub fn new(questions: Vec<Question>, answers: Vec<Answer>) -> Self {
+        Self { questions, answers }
+    }
+
+    /// The questions and answers of the HPOM survey
+    pub fn builtin() -> Self {
+        let q = |id, prompt| Question { id, prompt };
+        let a = |button, question, label| Answer { button, question, label };
+        Self::new(
+            vec![
+                q("role", "Role"),
+                q("question_type", "Question type"),
+                q("team_size", "Preferred team size"),
+                q("role_pref", "Wants to see more"),
+                q("hpom_live", "Believes HPOM has been live for two years"),
+                q("richard_cai", "Not intimidated by Richard Cai"),
+            ],
+            vec![
+                // Page 2
+                a("pm", "role", "Product Manager"),
+                a("ux", "role", "UX Designer"),
+                a("engi", "role", "Engineer"),
+                a("dm", "role", "Developer Manager"),
+                // Page 3
+                a("mc", "question_type", "Multiple Choice"),
+                a("tf", "question_type", "True/False"),
+                // Page 4
+                a("4a", "team_size", "3-5 people"),
+                a("4b", "team_size", "6-8 people"),
+                a("4c", "team_size", "9-12 people"),
+                a("4d", "team_size", "13-15 people"),
+                // Page 5
+                a("5t", "hpom_live", "True"),
+                a("5f", "hpom_live", "False"),
+                // Page 6
+                a("6a", "role_pref", "Product Manager"),
+                a("6b", "role_pref", "Developer Manager"),
+                a("6c", "role_pref", "Engineer"),
+                a("6d", "role_pref", "UX Designer"),
+                // Page 7
+                a("7t", "richard_cai", "True"),
+                a("7f", "richard_cai", "False"),
+            ],
+        )
+    }
+
+    /// The questions, in survey order
+    pub fn questions(&self) -> &[Question] {
+        &self.questions
+    }
+
+    /// The possible answers to `question`, in registry order
+    pub fn answers_to<'a>(&'a self, question: &'a str) -> impl Iterator<Item = &'a Answer> + 'a {
+        self.answers.iter().filter(move |a| a.question == question)
+    }
+
+    /// Look up what a button press means, if it answers a question
+    pub fn answer(&self, button: &str) -> Option<&Answer> {
+        self.answers.iter().find(|a| a.button == button)
+    }
+
+    /// Label for each question in registry order, or `None` if unanswered
+    pub fn answers_for<'a>(&'a self, button_presses: &[String]) -> Vec<(&'a Question, Option<&'a str>)> {
+        self.questions
+            .iter()
+            .map(|question| {
+                let label = button_presses
+                    .iter()
+                    .filter_map(|button| self.answer(button))
+                    .find(|answer| answer.question == question.id)
+                    .map(|answer| answer.label);
+                (question, label)
+            })
+            .collect()
+    }
+
+    /// Column names for the response CSV
+    pub fn csv_columns(&self) -> Vec<&str> {
+        let mut columns = vec![
+            SESSION_COLUMN,
+            STATUS_COLUMN,
+            LAST_PAGE_COLUMN,
+            RECORDED_AT_COLUMN,
+            STARTED_AT_COLUMN,
+            FINISHED_AT_COLUMN,
+            DEVICE_COLUMN,
+            PAGE_DWELL_COLUMN,
+        ];
+        columns.extend(self.questions.iter().map(|q| q.id));
+        columns.push(DOC_STRING_COLUMN);
+        columns
+    }
+
+    /// Human-readable heading for a CSV column
+    pub fn column_title<'a>(&'a self, column: &'a str) -> &'a str {
+        match column {
+            SESSION_COLUMN => "Session",
+            STATUS_COLUMN => "Status",
+            LAST_PAGE_COLUMN => "Last page",
+            RECORDED_AT_COLUMN => "Recorded at",
+            STARTED_AT_COLUMN => "Started at",
+            FINISHED_AT_COLUMN => "Last answer at",
+            DEVICE_COLUMN => "Device",
+            PAGE_DWELL_COLUMN => "Seconds per page",
+            DOC_STRING_COLUMN => "Summary",
+            _ => self.questions.iter().find(|q| q.id == column).map_or(column, |q| q.prompt),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn labels_answers_in_question_order() {
+        let labels = LabelRegistry::builtin();
+        let presses = ["start", "tf", "engi", "5f", "unknown"].map(String::from);
+        let answers: Vec<(&str, Option<&str>)> = labels.answers_for(&presses).into_iter().map(|(q, label)| (q.id, label)).collect();
+        assert_eq!(
+            answers,
+            [
+                ("role", Some("Engineer")),
+                ("question_type", Some("True/False")),
+                ("team_size", None),
+                ("role_pref", None),
+                ("hpom_live", Some("False")),
+                ("richard_cai", None),
+            ]
+        );
+    }
+
+    #[test]
+    fn builds_columns_and_titles() {
+        let labels = LabelRegistry::builtin();
+        let columns = labels.csv_columns();
+        assert_eq!(columns[..8], ["session_id", "status", "last_page", "recorded_at", "started_at", "finished_at", "device", "page_dwell_secs"]);
+        assert_eq!(columns[8..], ["role", "question_type", "team_size", "role_pref", "hpom_live", "richard_cai", "doc_string"]);
+        assert_eq!(labels.column_title("session_id"), "Session");
+        assert_eq!(labels.column_title("page_dwell_secs"), "Seconds per page");
+        assert_eq!(labels.column_title("team_size"), "Preferred team size");
+        assert_eq!(labels.column_title("doc_string"), "Summary");
+        // Columns from other schema versions are shown as they are
+        assert_eq!(labels.column_title("legacy"), "legacy");
+    }
+}
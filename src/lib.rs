@@ -1 +1,4 @@
-pub mod user_session;
\ No newline at end of file
+pub mod device;
+pub mod flow;
+pub mod labels;
+pub mod user_session;
@@ -1,273 +1,623 @@
-use std::sync::atomic::{AtomicUsize, Ordering};
-fn try_write_session_to_csv(
-    session_id: &str,
-    role: &str,
-    qtype: &str,
-    team_size: &str,
-    role_pref: &str,
-    hpom_live: &str,
-    richard_cai: &str,
-    doc_string: &str,
-) {
-    let csv_path = "/data/data.csv";
-    if !std::path::Path::new(csv_path).exists() {
-        println!("[DEBUG] Volume not attached or /data/data.csv does not exist. Skipping CSV write.");
-        return;
-    }
-    println!("[DEBUG] Attempting to write to CSV at {}", csv_path);
-    if !std::path::Path::new("/data").exists() {
-        println!("[DEBUG] /data directory does not exist!");
-    } else {
-        println!("[DEBUG] /data directory exists.");
-    }
-    let mut add_header = false;
-    if let Ok(metadata) = std::fs::metadata(csv_path) {
-        if metadata.len() == 0 {
-            add_header = true;
-        }
-    }
-    let mut file = std::fs::OpenOptions::new()
-        .create(true)
-        .append(true)
-        .open(csv_path)
-        .unwrap_or_else(|e| {
-            println!("[DEBUG] Failed to open {}: {}", csv_path, e);
-            panic!("[DEBUG] Could not open CSV file");
-        });
-    if add_header {
-        let header = "session_id,role,question_type,team_size,role_pref,hpom_live,richard_cai,doc_string\n";
-        use std::io::Write;
-        if let Err(e) = file.write_all(header.as_bytes()) {
-            println!("[DEBUG] Failed to write header to {}: {}", csv_path, e);
-        } else {
-            println!("[DEBUG] Wrote header to {}", csv_path);
-        }
-    }
-    let row = format!(
-        "{},{},{},{},{},{},{},\"{}\"\n",
-        session_id,
-        role,
-        qtype,
-        team_size,
-        role_pref,
-        hpom_live,
-        richard_cai,
-        doc_string
-    );
-    use std::io::Write;
-    if let Err(e) = file.write_all(row.as_bytes()) {
-        println!("[DEBUG] Failed to write row to {}: {}", csv_path, e);
-    } else {
-        println!("[DEBUG] Successfully wrote row to {}", csv_path);
-    }
-}
 use std::{
-    collections::HashMap,
     fs,
     io::{BufReader, prelude::*},
     net::{TcpListener, TcpStream},
     sync::{Arc, Mutex},
     thread,
+    time::{Duration, SystemTime},
 };
 
+mod admin;
+mod archive;
+mod assets;
+mod config;
+mod csv;
+mod device;
+mod export;
+mod flow;
+mod http;
+mod labels;
+mod pool;
+mod response_db;
+mod response_store;
+mod results;
+mod schema;
+mod session_store;
+mod shutdown;
+mod static_files;
+mod template;
+mod timestamp;
 mod user_session;
-use user_session::UserSession;
+use admin::{AdminAuth, AuthResult};
+use assets::Assets;
+use config::{Command, Config, Storage};
+use device::DeviceClass;
+use flow::SurveyFlow;
+use http::{Request, Response};
+use labels::LabelRegistry;
+use pool::ThreadPool;
+use response_db::DbStore;
+use response_store::{CsvStore, ResponseStore};
+use results::Results;
+use session_store::{FileStore, SessionStore};
+use static_files::StaticFiles;
+use template::{Value, html_escape, map};
+use user_session::{ResponseStatus, UserSession};
+
+type Sessions = Mutex<Box<dyn SessionStore>>;
 
-type Sessions = Arc<Mutex<HashMap<String, UserSession>>>;
+/// State shared by every connection
+struct AppState {
+    sessions: Sessions,
+    flow: SurveyFlow,
+    labels: LabelRegistry,
+    /// Where finished, restarted and abandoned sessions are recorded
+    responses: Box<dyn ResponseStore>,
+    admin: AdminAuth,
+    /// Images and other assets under `/lib/`
+    assets: Assets,
+    lib_files: StaticFiles,
+    /// The shared stylesheet under `/static/`
+    static_files: StaticFiles,
+    config: Config,
+}
 
 fn main() {
+    let command = Command::parse(std::env::args().skip(1), |name| std::env::var(name).ok());
+    let config = match command {
+        Ok(Command::Serve(config)) => config,
+        Ok(Command::PrintConfig(config)) => {
+            print!("{}", config);
+            return;
+        }
+        Ok(Command::ImportCsv(config)) => {
+            if let Err(e) = import_csv(&config, &LabelRegistry::builtin()) {
+                eprintln!("[ERROR] Import failed: {}", e);
+                std::process::exit(1);
+            }
+            return;
+        }
+        Ok(Command::Help) => {
+            print!("{}", config::usage());
+            return;
+        }
+        Err(e) => {
+            eprintln!("[ERROR] {}\n\n{}", e, config::usage());
+            std::process::exit(2);
+        }
+    };
     // TODO figure out a way to log all information, fly.io volumes?
-    let sessions: Sessions = Arc::new(Mutex::new(HashMap::new()));
-    const MAX_THREADS: usize = 64;
-    static THREAD_COUNT: AtomicUsize = AtomicUsize::new(0);
-    // Use 0.0.0.0:8080 on Fly.io, otherwise use 127.0.0.1:7878
-    let bind_addr = if std::env::var("FLY_APP_NAME").is_ok() {
-        "0.0.0.0:8080"
+    let assets = if config.dev_mode {
+        Assets::Disk(config.static_root.clone())
     } else {
-        "127.0.0.1:7878"
+        Assets::Embedded
     };
-    let listener = TcpListener::bind(bind_addr).unwrap();
-    
-    for stream in listener.incoming() {
-        let stream = match stream {
-            Ok(s) => s,
+    println!("[DEBUG] Serving pages from {}", assets.describe());
+    let Some(flow_text) = assets.get_string("flow.toml") else {
+        eprintln!("[ERROR] flow.toml not found in {}", assets.describe());
+        std::process::exit(1);
+    };
+    let flow = match SurveyFlow::parse(&flow_text) {
+        Ok(flow) => flow,
+        Err(e) => {
+            eprintln!("[ERROR] Invalid survey flow in flow.toml: {}", e);
+            std::process::exit(1);
+        }
+    };
+    let labels = LabelRegistry::builtin();
+    let responses = match open_response_store(&config, &labels) {
+        Ok(responses) => responses,
+        Err(e) => {
+            eprintln!("[ERROR] Could not open the responses in {}: {}", config.data_dir.display(), e);
+            std::process::exit(1);
+        }
+    };
+    println!("[DEBUG] Recording responses in {}", responses.describe());
+    let sessions = open_session_store(&config, &flow);
+    let state = Arc::new(AppState {
+        sessions: Mutex::new(sessions),
+        flow,
+        labels,
+        responses,
+        admin: AdminAuth::from_env(),
+        assets,
+        lib_files: StaticFiles::new("lib", LIB_CACHE_CONTROL),
+        static_files: StaticFiles::new("static", STATIC_CACHE_CONTROL),
+        config,
+    });
+    spawn_session_reaper(Arc::clone(&state));
+    let bind_addr = state.config.bind_addr();
+    let listener = match TcpListener::bind(bind_addr) {
+        Ok(listener) => listener,
+        Err(e) => {
+            eprintln!("[ERROR] Could not listen on {}: {}", bind_addr, e);
+            std::process::exit(1);
+        }
+    };
+    println!("[DEBUG] Listening on {}", bind_addr);
+    // Poll instead of blocking in accept() so a shutdown signal is noticed promptly
+    listener.set_nonblocking(true).unwrap();
+    shutdown::install_handlers();
+
+    let pool_state = Arc::clone(&state);
+    let queue_capacity = state.config.max_connections * QUEUE_SLOTS_PER_WORKER;
+    let pool = ThreadPool::new(state.config.max_connections, queue_capacity, move |stream| {
+        handle_connection(stream, &pool_state)
+    });
+
+    accept_connections(&listener, &pool, shutdown::requested);
+
+    // Stop accepting, then drain requests that are already queued or in progress
+    println!("[DEBUG] Shutdown requested, finishing in-flight requests");
+    drop(listener);
+    // Sessions are already journaled as they change, so there is nothing left to save
+    pool.shutdown();
+}
+
+const ACCEPT_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Hands connections from the non-blocking `listener` to the pool until
+/// `stop` returns true, turning clients away while the queue is full
+fn accept_connections(listener: &TcpListener, pool: &ThreadPool<TcpStream>, stop: impl Fn() -> bool) {
+    while !stop() {
+        let stream = match listener.accept() {
+            Ok((s, _)) => s,
+            Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {
+                thread::sleep(ACCEPT_POLL_INTERVAL);
+                continue;
+            }
             Err(e) => {
                 eprintln!("[ERROR] Failed to accept connection: {}", e);
                 continue;
             }
         };
-        if THREAD_COUNT.load(Ordering::SeqCst) >= MAX_THREADS {
-            eprintln!("[WARN] Max concurrent connections reached ({}). Dropping connection.", MAX_THREADS);
-            // Optionally: stream.shutdown(Shutdown::Both).ok();
+        if let Err(e) = stream.set_nonblocking(false) {
+            eprintln!("[ERROR] Failed to configure connection: {}", e);
             continue;
         }
-        THREAD_COUNT.fetch_add(1, Ordering::SeqCst);
-        let sessions = Arc::clone(&sessions);
-        thread::spawn(move || {
-            handle_connection(stream, sessions);
-            THREAD_COUNT.fetch_sub(1, Ordering::SeqCst);
-        });
+        if let Err(stream) = pool.try_submit(stream) {
+            eprintln!("[WARN] Request queue full. Rejecting connection.");
+            reject_overloaded(stream);
+        }
     }
 }
 
-fn handle_connection(mut stream: TcpStream, sessions: Sessions) {
-    let buf_reader = BufReader::new(&stream);
-    let http_request: Vec<String> = buf_reader
-        .lines()
-        .map(|result| result.unwrap())
-        .take_while(|line| !line.is_empty())
-        .collect();
-    
-    let request_line = &http_request[0];
+/// Records a finished, restarted or abandoned session as one response row
+fn record_response(state: &AppState, session_id: &str, session: &UserSession, status: ResponseStatus) {
+    let labels = &state.labels;
+    let mut fields = vec![
+        session_id.to_string(),
+        status.as_str().to_string(),
+        session.current_page().to_string(),
+        timestamp::format_rfc3339(SystemTime::now()),
+        timestamp::format_rfc3339(session.started_at()),
+        session.finished_at().map(timestamp::format_rfc3339).unwrap_or_default(),
+        session.device().as_str().to_string(),
+        format_dwell_times(&session.page_dwell_times()),
+    ];
+    for (_, label) in labels.answers_for(session.button_presses()) {
+        fields.push(label.unwrap_or("").to_string());
+    }
+    fields.push(session.to_doc_string(labels));
+    match state.responses.append(&fields) {
+        Ok(()) => println!("[DEBUG] Recorded {} response for {}", status.as_str(), session_id),
+        Err(e) => eprintln!("[ERROR] Failed to record response in {}: {}", state.responses.describe(), e),
+    }
+}
 
-    let (status_line, contents, content_type, set_cookie) = if request_line.starts_with("GET /lib/") {
-        // Serve static files (images, etc.)
-        let path = request_line.split_whitespace().nth(1).unwrap().trim_start_matches('/');
-        match fs::read(path) {
-            Ok(bytes) => ("HTTP/1.1 200 OK".to_string(), bytes, get_content_type(path).to_string(), None),
-            Err(_) => ("HTTP/1.1 404 NOT FOUND".to_string(), Vec::new(), "text/plain".to_string(), None),
+/// Seconds spent on each page as `page=secs` pairs, e.g. `1=2.4;2=11.0`
+fn format_dwell_times(dwell_times: &[(usize, Duration)]) -> String {
+    dwell_times
+        .iter()
+        .map(|(page, dwell)| format!("{}={:.1}", page, dwell.as_secs_f64()))
+        .collect::<Vec<_>>()
+        .join(";")
+}
+
+/// Opens the configured backend, creating its files so a bad location fails
+/// at startup rather than on every response
+fn open_response_store(config: &Config, labels: &LabelRegistry) -> std::io::Result<Box<dyn ResponseStore>> {
+    Ok(match config.storage {
+        Storage::Csv => Box::new(CsvStore::open(&config.csv_path(), &labels.csv_columns(), &config.archive_dir())?),
+        Storage::Db => Box::new(DbStore::open(&config.response_db_path(), &labels.csv_columns())?),
+    })
+}
+
+/// Copies every response in `data.csv` into an empty response database
+fn import_csv(config: &Config, labels: &LabelRegistry) -> std::io::Result<()> {
+    let db = DbStore::open(&config.response_db_path(), &labels.csv_columns())?;
+    if db.row_count()? > 0 {
+        return Err(std::io::Error::other(format!("{} already has responses", config.response_db_path().display())));
+    }
+    let page = CsvStore::open(&config.csv_path(), &labels.csv_columns(), &config.archive_dir())?.query(&export::Query::all())?;
+    let imported = db.import(&page)?;
+    println!("[DEBUG] Imported {} response(s) from {} into {}", imported, config.csv_path().display(), config.response_db_path().display());
+    Ok(())
+}
+
+/// Journals sessions next to the CSV, so users keep their place across restarts
+fn open_session_store(config: &Config, flow: &SurveyFlow) -> Box<dyn SessionStore> {
+    let journal_path = config.session_journal_path();
+    match FileStore::open(&journal_path, flow) {
+        Ok(store) => {
+            println!("[DEBUG] Restored {} session(s) from {}", store.iter().count(), journal_path.display());
+            Box::new(store)
         }
-    } else {
-        handle_app_request(http_request.join("\n"), sessions)
-    };
+        Err(e) => {
+            eprintln!("[ERROR] Failed to open session journal {}: {}", journal_path.display(), e);
+            std::process::exit(1);
+        }
+    }
+}
 
-    let length = contents.len();
-    let mut response = format!("{status_line}\r\nContent-Length: {length}\r\nContent-Type: {content_type}\r\n");
-    
-    if let Some(cookie) = set_cookie {
-        response.push_str(&format!("Set-Cookie: {}\r\n", cookie));
+/// Connections that may wait for a free worker, per worker
+const QUEUE_SLOTS_PER_WORKER: usize = 4;
+
+/// Tells a client we're too busy instead of silently dropping it
+fn reject_overloaded(mut stream: TcpStream) {
+    let response = Response::text("503 SERVICE UNAVAILABLE", "Server busy, please retry.")
+        .with_header("Retry-After", "1")
+        .with_header("Connection", "close");
+    // Don't let a slow client stall the accept loop
+    let _ = stream.set_write_timeout(Some(Duration::from_secs(1)));
+    let _ = stream.write_all(&response.to_bytes());
+}
+
+/// Periodically evicts sessions that have been idle for longer than the
+/// session TTL and compacts the session store. If `log_abandoned` is set,
+/// evicted sessions that answered at least one button are recorded as
+/// "abandoned" rows.
+fn spawn_session_reaper(state: Arc<AppState>) {
+    let interval = (state.config.session_ttl / 2).min(Duration::from_secs(60));
+    thread::spawn(move || loop {
+        thread::sleep(interval);
+        reap_idle_sessions(&state, SystemTime::now());
+    });
+}
+
+/// Evicts sessions idle as of `now` and records the abandoned ones
+fn reap_idle_sessions(state: &AppState, now: SystemTime) {
+    let evicted = {
+        let mut sessions = state.sessions.lock().unwrap();
+        let evicted = sessions.remove_idle(now, state.config.session_ttl);
+        if let Err(e) = sessions.compact() {
+            eprintln!("[ERROR] Failed to compact session store: {}", e);
+        }
+        evicted
+    };
+    if evicted.is_empty() {
+        return;
     }
-    
-    response.push_str("\r\n");
-    stream.write_all(response.as_bytes()).unwrap();
-    stream.write_all(&contents).unwrap();
+    println!("[DEBUG] Evicted {} idle session(s)", evicted.len());
+    if state.config.log_abandoned {
+        // Sessions on the terminal page were already recorded as completed
+        let abandoned = evicted
+            .iter()
+            .filter(|(_, s)| !s.button_presses().is_empty() && s.current_page() != state.flow.terminal_page());
+        for (session_id, session) in abandoned {
+            record_response(state, session_id, session, ResponseStatus::Abandoned);
+        }
+    }
+}
+
+/// How long a worker waits for the first request on a new connection
+const READ_TIMEOUT: Duration = Duration::from_secs(10);
+/// How long an idle keep-alive connection may hold a worker between requests
+const KEEP_ALIVE_TIMEOUT: Duration = Duration::from_secs(5);
+/// Requests served on one connection before we close it
+const MAX_REQUESTS_PER_CONNECTION: usize = 100;
+
+/// Serves requests from one connection until the client asks to close, goes
+/// idle, or hits the per-connection cap. Pipelined requests are answered in
+/// order because they sit in the same buffered reader.
+fn handle_connection(stream: TcpStream, state: &AppState) {
+    serve_connection(stream, state, READ_TIMEOUT, KEEP_ALIVE_TIMEOUT);
 }
 
-fn handle_app_request(request: String, sessions: Sessions) -> (String, Vec<u8>, String, Option<String>) {
-    let request_line = request.lines().next().unwrap();
-    println!("Request: {}", request_line);
-
-    let parts: Vec<&str> = request_line.split_whitespace().collect();
-    if parts.len() < 2 {
-        return ("HTTP/1.1 400 BAD REQUEST".to_string(), Vec::new(), "text/plain".to_string(), None);
-    }
-
-    // Endpoint to clear the CSV data
-    if parts[1] == "/clear-data" {
-        let csv_path = "/data/data.csv";
-        let result = std::fs::OpenOptions::new()
-            .write(true)
-            .truncate(true)
-            .open(csv_path)
-            .and_then(|mut file| file.write_all(b"session_id,role,question_type,team_size,role_pref,hpom_live,richard_cai,doc_string\n"));
-        let html = match result {
-            Ok(_) => "<html><body><h2>CSV data cleared.</h2></body></html>".to_string(),
-            Err(_) => "<html><body><h2>Failed to clear CSV data (file not found or volume not attached).</h2></body></html>".to_string(),
+/// `handle_connection` with the wait for the first request and between
+/// later ones given explicitly
+fn serve_connection(stream: TcpStream, state: &AppState, first_timeout: Duration, idle_timeout: Duration) {
+    let mut buf_reader = BufReader::new(&stream);
+    let mut writer = &stream;
+    for served in 1..=MAX_REQUESTS_PER_CONNECTION {
+        let timeout = if served == 1 { first_timeout } else { idle_timeout };
+        if let Err(e) = stream.set_read_timeout(Some(timeout)) {
+            println!("[DEBUG] Failed to set read timeout: {}", e);
+            return;
+        }
+        let (response, keep_alive) = match Request::read_from(&mut buf_reader) {
+            Ok(request) => {
+                let keep_alive = request.wants_keep_alive()
+                    && served < MAX_REQUESTS_PER_CONNECTION
+                    && !shutdown::requested();
+                (route_request(&request, state), keep_alive)
+            }
+            Err(e) => {
+                println!("[DEBUG] Could not parse request: {}", e);
+                match e.response() {
+                    Some(response) => (response, false),
+                    None => return,
+                }
+            }
+        };
+        let response = if keep_alive {
+            response
+                .with_header("Connection", "keep-alive")
+                .with_header("Keep-Alive", format!("timeout={}", idle_timeout.as_secs()))
+        } else if response.header("Connection").is_none() {
+            response.with_header("Connection", "close")
+        } else {
+            response
         };
-        return ("HTTP/1.1 200 OK".to_string(), html.into_bytes(), "text/html".to_string(), None);
+
+        if let Err(e) = writer.write_all(&response.to_bytes()) {
+            println!("[DEBUG] Failed to write response: {}", e);
+            return;
+        }
+        if !keep_alive {
+            return;
+        }
     }
+}
 
-    // Pretty CSV view endpoint
-    if parts[1] == "/view-data" {
-        let csv_path = "/data/data.csv";
-        let html = match std::fs::read_to_string(csv_path) {
-            Ok(csv) => csv_to_html_table(&csv),
-            Err(_) => "<html><body><h2>CSV file not found or volume not attached.</h2></body></html>".to_string(),
-        };
-        return ("HTTP/1.1 200 OK".to_string(), html.into_bytes(), "text/html".to_string(), None);
+/// The images under `/lib/` are replaced rarely and never renamed, so
+/// browsers may reuse them for a week and revalidate with the ETag after
+const LIB_CACHE_CONTROL: &str = "public, max-age=604800";
+
+/// `app.css` keeps its name when it changes, so browsers revalidate it on
+/// every page load; the ETag makes that a 304 when nothing changed
+const STATIC_CACHE_CONTROL: &str = "no-cache";
+
+fn route_request(request: &Request, state: &AppState) -> Response {
+    if request.method == "GET"
+        && let Some(path) = request.path().strip_prefix("/lib/") {
+        // Serve static files (images, etc.)
+        return state.lib_files.serve(&state.assets, path, request.header("if-none-match"));
     }
+    if request.method == "GET"
+        && let Some(path) = request.path().strip_prefix("/static/") {
+        return state.static_files.serve(&state.assets, path, request.header("if-none-match"));
+    }
+    handle_app_request(request, state)
+}
 
-    let url_parts: Vec<&str> = parts[1].split('?').collect();
-    let query = if url_parts.len() > 1 { Some(url_parts[1]) } else { None };
+fn with_cookie(response: Response, set_cookie: Option<String>) -> Response {
+    match set_cookie {
+        Some(cookie) => response.with_header("Set-Cookie", cookie),
+        None => response,
+    }
+}
 
-    // Only allow /, /pageN, /view-data, /clear-data, and static files
-    let allowed = parts[1] == "/"
-        || parts[1].starts_with("/page")
-        || parts[1] == "/view-data"
-        || parts[1] == "/clear-data"
-        || parts[1].starts_with("/lib/");
-    if !allowed {
-        let html = std::fs::read_to_string("404.html").unwrap_or_else(|_| "<html><body><h1>404 Not Found</h1></body></html>".to_string());
-        return ("HTTP/1.1 404 NOT FOUND".to_string(), html.into_bytes(), "text/html".to_string(), None);
-    }
-fn csv_to_html_table(csv: &str) -> String {
-    let mut lines = csv.lines();
-    let header = lines.next();
-    let mut html = String::from("<html><head><title>Survey Data</title><style>table{border-collapse:collapse;}th,td{border:1px solid #ccc;padding:6px;}th{background:#f0f0f0;}</style></head><body><h2>Survey Data</h2><table>");
-    if let Some(h) = header {
-        html.push_str("<tr>");
-        for col in h.split(',') {
-            html.push_str(&format!("<th>{}</th>", html_escape(col)));
+/// Admin routes. Every one of them requires credentials, and the
+/// destructive clear only runs on a confirmed POST so crawlers and link
+/// previews can't trigger it.
+fn handle_admin_request(request: &Request, state: &AppState) -> Response {
+    let (method, path, query) = (request.method.as_str(), request.path(), request.query());
+    match state.admin.check(request.header("authorization")) {
+        AuthResult::Allowed => {}
+        AuthResult::Denied => {
+            println!("[WARN] Rejected unauthenticated request for {}", path);
+            return Response::text("401 UNAUTHORIZED", "Admin credentials required.")
+                .with_header("WWW-Authenticate", "Basic realm=\"hpom-admin\", charset=\"UTF-8\"");
+        }
+        AuthResult::Disabled => {
+            return Response::text(
+                "403 FORBIDDEN",
+                "Admin routes are disabled. Set ADMIN_TOKEN or ADMIN_USER and ADMIN_PASSWORD to enable them.",
+            );
         }
-        html.push_str("</tr>");
     }
-    for line in lines {
-        html.push_str("<tr>");
-        let mut in_quotes = false;
-        let mut cell = String::new();
-        for c in line.chars() {
-            match c {
-                '"' => in_quotes = !in_quotes,
-                ',' if !in_quotes => {
-                    html.push_str(&format!("<td>{}</td>", html_escape(&cell)));
-                    cell.clear();
-                },
-                _ => cell.push(c),
+
+    match (method, path) {
+        // Pretty table of every response
+        ("GET", "/view-data") => {
+            let html = match state.responses.query(&export::Query::all()) {
+                Ok(page) => page_to_html_table(&page, &state.labels),
+                Err(e) => format!("<html><body><h2>Could not read the responses: {}</h2></body></html>", html_escape(&e.to_string())),
+            };
+            Response::html("200 OK", html)
+        }
+        // Aggregated charts of the same data
+        ("GET", "/results") => match state.responses.query(&export::Query::all()) {
+            Ok(page) => Response::html("200 OK", Results::tally(&page, &state.labels).to_html()),
+            Err(e) => Response::text("500 INTERNAL SERVER ERROR", &format!("Could not read the responses: {}", e)),
+        },
+        // Filtered, paginated responses as JSON
+        ("GET", "/api/responses") => {
+            let page = match export_page(state, query, Some(export::DEFAULT_JSON_LIMIT)) {
+                Ok(page) => page,
+                Err((status, message)) => {
+                    let body = format!("{{\"error\":{}}}", export::json_string(&message));
+                    return Response::new(status, "application/json", body.into_bytes());
+                }
+            };
+            let mut response = Response::new("200 OK", "application/json", page.to_json().into_bytes());
+            if let Some(cursor) = page.next_cursor {
+                response = response.with_header("Link", format!("</api/responses?{}>; rel=\"next\"", export::Query::with_cursor(query, cursor)));
             }
+            response
         }
-        html.push_str(&format!("<td>{}</td>", html_escape(&cell)));
-        html.push_str("</tr>");
+        // The same responses as a CSV download; every match unless a limit is given
+        ("GET", "/export.csv") => {
+            let page = match export_page(state, query, None) {
+                Ok(page) => page,
+                Err((status, message)) => return Response::text(status, &message),
+            };
+            let filename = format!("responses-{}.csv", timestamp::format_compact(SystemTime::now()));
+            let mut response = Response::new("200 OK", "text/csv; charset=utf-8", page.to_csv().into_bytes())
+                .with_header("Content-Disposition", format!("attachment; filename=\"{}\"", filename));
+            if let Some(cursor) = page.next_cursor {
+                response = response
+                    .with_header("X-Next-Cursor", cursor.to_string())
+                    .with_header("Link", format!("</export.csv?{}>; rel=\"next\"", export::Query::with_cursor(query, cursor)));
+            }
+            response
+        }
+        // Confirmation page for clearing the data
+        ("GET", "/clear-data") => Response::html(
+            "200 OK",
+            "<html><body><h2>Clear all survey data?</h2><p>The current responses are moved to the \
+             <a href=\"/admin/archive\">archive</a> and recording starts afresh.</p>\
+             <form method=\"post\" action=\"/clear-data?confirm=yes\"><button type=\"submit\">Clear data</button></form></body></html>"
+                .to_string(),
+        ),
+        // Endpoint to clear the data: archive the current responses, then start empty
+        ("POST", "/clear-data") => {
+            if query_param(query, "confirm") != Some("yes") {
+                return Response::text("400 BAD REQUEST", "Clearing data requires confirm=yes.");
+            }
+            let html = match state.responses.archive(&state.config.archive_dir()) {
+                Ok(name) => format!(
+                    "<html><body><h2>Survey data cleared.</h2><p>Previous data archived as <a href=\"/admin/archive/{0}\">{0}</a>.</p></body></html>",
+                    html_escape(&name)
+                ),
+                Err(e) => {
+                    eprintln!("[ERROR] Failed to clear survey data: {}", e);
+                    format!("<html><body><h2>Failed to clear survey data: {}</h2></body></html>", html_escape(&e.to_string()))
+                }
+            };
+            Response::html("200 OK", html)
+        }
+        ("GET", "/admin/archive") => match archive::list(&state.config.archive_dir()) {
+            Ok(snapshots) => Response::html("200 OK", archive_to_html(&snapshots)),
+            Err(e) => Response::text("500 INTERNAL SERVER ERROR", &format!("Could not list archive: {}", e)),
+        },
+        ("GET", _) if path.starts_with("/admin/archive/") => {
+            let name = &path["/admin/archive/".len()..];
+            match archive::snapshot_path(&state.config.archive_dir(), name).map(std::fs::read) {
+                Some(Ok(bytes)) => Response::new("200 OK", "text/csv", bytes)
+                    .with_header("Content-Disposition", format!("attachment; filename=\"{}\"", name)),
+                Some(Err(e)) => Response::text("500 INTERNAL SERVER ERROR", &format!("Could not read snapshot: {}", e)),
+                None => Response::text("404 NOT FOUND", "No such snapshot."),
+            }
+        }
+        ("POST", _) if path.starts_with("/admin/archive/") => {
+            let name = path.strip_prefix("/admin/archive/").and_then(|p| p.strip_suffix("/restore"));
+            let Some(name) = name.filter(|name| !name.is_empty()) else {
+                return Response::text("404 NOT FOUND", "");
+            };
+            if query_param(query, "confirm") != Some("yes") {
+                return Response::text("400 BAD REQUEST", "Restoring a snapshot requires confirm=yes.");
+            }
+            match state.responses.restore(&state.config.archive_dir(), name) {
+                Ok(archived) => {
+                    let previous = archived
+                        .map(|a| format!("<p>Previous data archived as {}.</p>", html_escape(&a)))
+                        .unwrap_or_default();
+                    Response::html(
+                        "200 OK",
+                        format!("<html><body><h2>Restored {}.</h2>{}</body></html>", html_escape(name), previous),
+                    )
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => Response::text("404 NOT FOUND", "No such snapshot."),
+                Err(e) => Response::text("500 INTERNAL SERVER ERROR", &format!("Could not restore snapshot: {}", e)),
+            }
+        }
+        (_, "/clear-data") => Response::text("405 METHOD NOT ALLOWED", "").with_header("Allow", "GET, POST"),
+        (_, "/view-data" | "/results" | "/api/responses" | "/export.csv" | "/admin/archive") => Response::text("405 METHOD NOT ALLOWED", "").with_header("Allow", "GET"),
+        _ => Response::text("404 NOT FOUND", ""),
     }
-    html.push_str("</table></body></html>");
-    html
 }
 
-fn html_escape(s: &str) -> String {
-    s.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
-}
+fn handle_app_request(request: &Request, state: &AppState) -> Response {
+    let flow = &state.flow;
+    println!("Request: {} {} {}", request.method, request.target, request.version);
+
+    let path = request.path();
+    let admin_only = ["/clear-data", "/view-data", "/results", "/api/responses", "/export.csv"];
+    if admin_only.contains(&path) || path.starts_with("/admin/") {
+        return handle_admin_request(request, state);
+    }
 
-    // Extract session ID from cookie
-    let session_id = extract_session_id(&request).unwrap_or_else(|| generate_session_id());
-    println!("[DEBUG] Extracted session ID: {:?}", extract_session_id(&request));
+    // Only allow /, /pageN and static files; admin routes are handled above
+    let allowed = path == "/"
+        || path.starts_with("/page")
+        || path.starts_with("/lib/");
+    if !allowed {
+        let html = state.assets.get_string("404.html").unwrap_or_else(|| "<html><body><h1>404 Not Found</h1></body></html>".to_string());
+        return Response::html("404 NOT FOUND", html);
+    }
+    // Restarting changes state, so old `?restart=true` links must not trigger it
+    if request.method == "GET" && query_param(request.query(), "restart").is_some() {
+        return Response::text("405 METHOD NOT ALLOWED", "Restarting requires a POST.").with_header("Allow", "POST");
+    }
+
+    let mut sessions = state.sessions.lock().unwrap();
+
+    // Only adopt a cookie we issued ourselves; anything else gets a fresh ID
+    let known_session = extract_session_id(request).filter(|id| sessions.get(id).is_some());
+    let (session_id, set_cookie) = match known_session {
+        Some(id) => {
+            println!("[DEBUG] Session found, no new cookie needed");
+            (id, None)
+        }
+        None => {
+            let id = match generate_session_id() {
+                Ok(id) if sessions.get(&id).is_none() => id,
+                Ok(_) | Err(_) => {
+                    eprintln!("[ERROR] Could not generate a unique session ID");
+                    return Response::text("500 INTERNAL SERVER ERROR", "");
+                }
+            };
+            println!("[DEBUG] No session found, setting new cookie");
+            let cookie = format!("session_id={}; Path=/; HttpOnly; SameSite=Lax", id);
+            (id, Some(cookie))
+        }
+    };
     println!("[DEBUG] Using session ID: {}", session_id);
     
-    let set_cookie = if extract_session_id(&request).is_none() {
-        println!("[DEBUG] No session found, setting new cookie");
-        Some(format!("session_id={}; Path=/", session_id))
+    // State changes arrive as form POSTs; GET only renders the current page
+    let method = request.method.as_str();
+    let form = if method == "POST" {
+        if !has_form_content_type(request) {
+            return Response::text("415 UNSUPPORTED MEDIA TYPE", "Expected application/x-www-form-urlencoded.");
+        }
+        Some(String::from_utf8_lossy(&request.body).into_owned())
     } else {
-        println!("[DEBUG] Session found, no new cookie needed");
         None
     };
 
-    let mut sessions_guard = sessions.lock().unwrap();
-    
-    // Check for restart
-    if let Some(query) = &query {
-        if query.contains("restart=true") {
-            sessions_guard.remove(&session_id);
-        }
+    // Restarting is a state change too, so only the POST form can ask for it
+    let restart = query_param(form.as_deref(), "restart") == Some("true");
+    if restart
+        && let Some(old_session) = sessions.remove(&session_id)
+        && !old_session.button_presses().is_empty()
+        && old_session.current_page() != flow.terminal_page() {
+        record_response(state, &session_id, &old_session, ResponseStatus::Restarted);
     }
     
-    let session = sessions_guard.entry(session_id.clone()).or_insert(UserSession::new());
+    let device = DeviceClass::classify(request.header("user-agent"));
+    let current_page = sessions.get_or_create(&session_id, flow, device).current_page();
+    sessions.touch(&session_id);
+
+    // A finished survey stays on the terminal page until the user navigates elsewhere
+    if method == "GET"
+        && current_page == flow.terminal_page()
+        && path != format!("/page{}", flow.terminal_page()) {
+        sessions.reset(&session_id, flow);
+    }
+    let session = sessions.get(&session_id).expect("session was just created");
     
     // Debug: print session info before update
     println!("[DEBUG] Session ID: {}", session_id);
     println!("[DEBUG] Current page: {}", session.current_page());
     println!("[DEBUG] Button presses: {:?}", session.button_presses());
 
-    // Handle button press from query parameters
-    let mut remove_session = false;
-    if let Some(query) = query {
-        if let Some(button) = parse_button_press(query) {
+    if let Some(form) = form {
+        // Handle button press from the submitted form
+        if let Some(button) = parse_button_press(&form) {
             println!("[DEBUG] Attempting button press: '{}' from page {}", button, session.current_page());
             // Process the button press
-            match session.process_button_press(&button) {
+            let result = sessions.press_button(&session_id, flow, &button);
+            let session = sessions.get(&session_id).expect("session was just created");
+            match result {
                 Ok(next_page) => {
                     println!("Session {}: Button press '{}' validated! Moving to page {}", 
                              session_id, button, next_page);
                     println!("[DEBUG] Updated button presses: {:?}", session.button_presses());
-                    if next_page == 9 {
-                        remove_session = true;
+                    // Record the response once the user reaches the terminal page
+                    if next_page == flow.terminal_page() {
+                        record_response(state, &session_id, session, ResponseStatus::Completed);
                     }
                 },
                 Err(error) => {
@@ -283,157 +633,541 @@ fn html_escape(s: &str) -> String {
                             println!("[DEBUG] ERROR: Invalid page transition from page {}", page);
                         },
                     }
-                    // Don't update session, just serve the current page again
+                    // Don't update session, just redirect to the current page again
                 }
             }
         }
+        // POST-redirect-GET, so refreshing the page doesn't re-submit the form
+        let location = format!("/page{}", sessions.get(&session_id).expect("session was just created").current_page());
+        return with_cookie(Response::text("303 SEE OTHER", "").with_header("Location", location), set_cookie);
     }
 
-    // Remove session if user has reached page 9
-    if remove_session {
-        let user_session = sessions_guard.remove(&session_id).unwrap();
-        let bp = user_session.button_presses();
-        // Extract role, question_type, answers as in to_doc_string
-        let role = bp.get(1).map(|s| match s.as_str() {
-            "pm" => "Product Manager",
-            "ux" => "UX Designer",
-            "engi" => "Engineer",
-            "dm" => "Deveveloper Manager",
-            _ => s.as_str(),
-        }).unwrap_or("");
-        let qtype = bp.get(2).map(|s| match s.as_str() {
-            "mc" => "Multiple Choice",
-            "tf" => "True/False",
-            _ => s.as_str(),
-        }).unwrap_or("");
-        // MC answers
-        let mut team_size = "";
-        let mut role_pref = "";
-        if qtype == "Multiple Choice" {
-            if let Some(ans) = bp.get(3) {
-                team_size = match ans.as_str() {
-                    "4a" => "3-5 people",
-                    "4b" => "6-8 people",
-                    "4c" => "9-12 people",
-                    "4d" => "13-15 people",
-                    _ => "(unknown)",
-                };
-            }
-            if let Some(ans) = bp.get(4) {
-                role_pref = match ans.as_str() {
-                    "6a" => "Product Manager",
-                    "6b" => "Developer Manager",
-                    "6c" => "Engineer",
-                    "6d" => "UX Designer",
-                    _ => "(unknown)",
-                };
-            }
-        }
-        // TF answers
-        let mut hpom_live = "";
-        let mut richard_cai = "";
-        if qtype == "True/False" {
-            if let Some(ans) = bp.get(3) {
-                hpom_live = match ans.as_str() {
-                    "5t" => "True",
-                    "5f" => "False",
-                    _ => "(unknown)",
-                };
-            }
-            if let Some(ans) = bp.get(4) {
-                richard_cai = match ans.as_str() {
-                    "7t" => "True",
-                    "7f" => "False",
-                    _ => "(unknown)",
-                };
-            }
-        }
-        let doc_string = UserSession::to_doc_string(user_session.clone()).replace('\n', "\\n").replace('"', "'");
-        try_write_session_to_csv(
-            &session_id,
-            role,
-            qtype,
-            team_size,
-            role_pref,
-            hpom_live,
-            richard_cai,
-            &doc_string,
-        );
-        // Serve page 1 after session removal
-        let html = load_page_html(9, &[]);
-        return ("HTTP/1.1 200 OK".to_string(), html.into_bytes(), "text/html".to_string(), set_cookie);
-    }
-
-    let page_to_serve = session.current_page();
-    let html = load_page_html(page_to_serve, session.button_presses());
+    let html = render_page(state, session);
     
     // Debug: print all sessions after update
     println!("\n[DEBUG] All sessions after update: {{");
-    for (sid, sess) in sessions_guard.iter() {
+    for (sid, sess) in sessions.iter() {
         println!("  {} => Current page: {}, Button presses: {:?}", 
                  sid, sess.current_page(), sess.button_presses());
     }
     println!("}}\n");
     
-    ("HTTP/1.1 200 OK".to_string(), html.into_bytes(), "text/html".to_string(), set_cookie)
+    with_cookie(Response::html("200 OK", html), set_cookie)
 }
 
-fn extract_session_id(request: &str) -> Option<String> {
-    for line in request.lines() {
-        if line.to_lowercase().starts_with("cookie:") {
-            if let Some(start) = line.find("session_id=") {
-                let start = start + "session_id=".len();
-                let end = line[start..].find(';').unwrap_or(line.len() - start);
-                let session_id = line[start..start + end].to_string();
-                println!("[DEBUG] Extracted session_id: {}", session_id);
-                return Some(session_id);
+const SESSION_ID_PREFIX: &str = "session_";
+const SESSION_ID_BYTES: usize = 16;
+
+fn extract_session_id(request: &Request) -> Option<String> {
+    if let Some(cookie) = request.header("cookie") {
+        let cookies = cookie.split(';').map(str::trim);
+        for value in cookies.filter_map(|c| c.strip_prefix("session_id=")) {
+            if is_valid_session_id(value) {
+                println!("[DEBUG] Extracted session_id: {}", value);
+                return Some(value.to_string());
             }
+            println!("[DEBUG] Ignoring malformed session_id: {:?}", value);
         }
     }
     println!("[DEBUG] No session_id found in request");
     None
 }
 
-fn generate_session_id() -> String {
-    use std::time::{SystemTime, UNIX_EPOCH};
-    let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
-    format!("session_{}", timestamp)
+fn is_valid_session_id(id: &str) -> bool {
+    id.strip_prefix(SESSION_ID_PREFIX).is_some_and(|hex| {
+        hex.len() == SESSION_ID_BYTES * 2 && hex.bytes().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f'))
+    })
 }
 
-fn parse_button_press(query: &str) -> Option<String> {
-    // Parse query like "button=pm" or "action=pm"
-    for param in query.split('&') {
-        if let Some((key, value)) = param.split_once('=') {
-            if key == "button" || key == "action" {
-                return Some(value.to_string());
-            }
+/// 128 random bits from the kernel CSPRNG, hex encoded
+fn generate_session_id() -> std::io::Result<String> {
+    let mut bytes = [0u8; SESSION_ID_BYTES];
+    fs::File::open("/dev/urandom")?.read_exact(&mut bytes)?;
+    let hex: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+    Ok(format!("{}{}", SESSION_ID_PREFIX, hex))
+}
+
+fn page_to_html_table(page: &export::Page, labels: &LabelRegistry) -> String {
+    let mut html = String::from("<html><head><title>Survey Data</title><style>table{border-collapse:collapse;}th,td{border:1px solid #ccc;padding:6px;white-space:pre-line;}th{background:#f0f0f0;}</style></head><body><h2>Survey Data</h2><table>");
+    html.push_str("<tr>");
+    for col in &page.columns {
+        html.push_str(&format!("<th>{}</th>", html_escape(labels.column_title(col))));
+    }
+    html.push_str("</tr>");
+    for fields in &page.rows {
+        html.push_str("<tr>");
+        for field in fields {
+            html.push_str(&format!("<td>{}</td>", html_escape(field)));
+        }
+        html.push_str("</tr>");
+    }
+    html.push_str("</table></body></html>");
+    html
+}
+
+/// The page of responses an export request asks for, or the status and
+/// message to answer with
+fn export_page(state: &AppState, query: Option<&str>, default_limit: Option<usize>) -> Result<export::Page, (&'static str, String)> {
+    let query = export::Query::parse(query, &state.labels, default_limit).map_err(|e| ("400 BAD REQUEST", e.to_string()))?;
+    state.responses.query(&query).map_err(|e| ("500 INTERNAL SERVER ERROR", format!("Could not read the responses: {}", e)))
+}
+
+fn archive_to_html(snapshots: &[archive::Snapshot]) -> String {
+    let mut html = String::from("<html><head><title>Archived Data</title><style>table{border-collapse:collapse;}th,td{border:1px solid #ccc;padding:6px;}th{background:#f0f0f0;}form{margin:0;}</style></head><body><h2>Archived Data</h2>");
+    if snapshots.is_empty() {
+        html.push_str("<p>No snapshots yet.</p></body></html>");
+        return html;
+    }
+    html.push_str("<table><tr><th>Snapshot</th><th>Size</th><th></th></tr>");
+    for snapshot in snapshots {
+        let name = html_escape(&snapshot.name);
+        html.push_str(&format!(
+            "<tr><td><a href=\"/admin/archive/{0}\">{0}</a></td><td>{1} bytes</td>\
+             <td><form method=\"post\" action=\"/admin/archive/{0}/restore?confirm=yes\" \
+             onsubmit=\"return confirm('Replace the current data with {0}? The current data will be archived first.')\">\
+             <button type=\"submit\">Restore</button></form></td></tr>",
+            name, snapshot.size
+        ));
+    }
+    html.push_str("</table></body></html>");
+    html
+}
+
+fn query_param<'a>(query: Option<&'a str>, name: &str) -> Option<&'a str> {
+    query?
+        .split('&')
+        .filter_map(|param| param.split_once('='))
+        .find_map(|(key, value)| (key == name).then_some(value))
+}
+
+fn parse_button_press(form: &str) -> Option<String> {
+    // Parse form data like "button=pm" or "action=pm"
+    for param in form.split('&') {
+        if let Some((key, value)) = param.split_once('=')
+            && (key == "button" || key == "action") {
+            return Some(http::url_decode(value));
         }
     }
     None
 }
 
-fn load_page_html(page: usize, _button_presses: &[String]) -> String {
+fn has_form_content_type(request: &Request) -> bool {
+    request.header("content-type").is_some_and(|value| {
+        let mime = value.split(';').next().unwrap_or("").trim();
+        mime.eq_ignore_ascii_case("application/x-www-form-urlencoded")
+    })
+}
+
+/// Renders the session's current page as a template with `page_context`
+fn render_page(state: &AppState, session: &UserSession) -> String {
+    let page = session.current_page();
     let filename = format!("page{}.html", page);
-    match fs::read_to_string(&filename) {
+    let Some(source) = state.assets.get_string(&filename) else {
+        return format!("<html><body><h1>Page {} not found</h1></body></html>", page);
+    };
+    let context = page_context(session, &state.flow, &state.labels);
+    match template::render(&source, &context, &|name| state.assets.get_string(name)) {
         Ok(html) => html,
-        Err(_) => {
-            format!("<html><body><h1>Page {} not found</h1></body></html>", page)
+        Err(e) => {
+            eprintln!("[ERROR] Failed to render {}: {}", filename, e);
+            format!("<html><body><h1>Page {} could not be rendered</h1></body></html>", page)
         }
     }
 }
 
-fn get_content_type(path: &str) -> &str {
-    if path.ends_with(".jpg") || path.ends_with(".jpeg") {
-        "image/jpeg"
-    } else if path.ends_with(".png") {
-        "image/png"
-    } else if path.ends_with(".gif") {
-        "image/gif"
-    } else if path.ends_with(".css") {
-        "text/css"
-    } else if path.ends_with(".js") {
-        "application/javascript"
-    } else {
-        "application/octet-stream"
+/// Variables available to page templates:
+/// - `page`: the current page number
+/// - `presses`: the raw button IDs pressed so far
+/// - `answers`: a list of `{question, prompt, answer}` for each answered question
+/// - `answer`: answers by question ID, e.g. `answer.role`
+/// - `summary`: the same text as the CSV `doc_string`
+/// - `completed`: whether the session reached the terminal page
+fn page_context(session: &UserSession, flow: &SurveyFlow, labels: &LabelRegistry) -> template::Context {
+    let answered: Vec<_> = labels
+        .answers_for(session.button_presses())
+        .into_iter()
+        .filter_map(|(question, label)| label.map(|label| (question, label)))
+        .collect();
+    let mut context = template::Context::new();
+    context
+        .set("page", session.current_page())
+        .set("presses", session.button_presses().to_vec())
+        .set(
+            "answers",
+            answered
+                .iter()
+                .map(|(question, label)| map([("question", question.id), ("prompt", question.prompt), ("answer", *label)]))
+                .collect::<Vec<_>>(),
+        )
+        .set(
+            "answer",
+            Value::Map(answered.iter().map(|(question, label)| (question.id.to_string(), Value::from(*label))).collect()),
+        )
+        .set("summary", session.to_doc_string(labels))
+        .set("completed", session.current_page() == flow.terminal_page());
+    context
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use session_store::MemoryStore;
+    use std::collections::HashMap;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::mpsc;
+    use std::time::Instant;
+
+    const ADMIN_TOKEN: &str = "tok123";
+
+    /// App state with in-memory sessions and a fresh data directory.
+    /// `args` are extra command-line settings.
+    fn test_state(name: &str, args: &[&str]) -> AppState {
+        let dir = std::env::temp_dir().join(format!("hpom-main-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let mut all = vec!["--data-dir".to_string(), dir.display().to_string()];
+        all.extend(args.iter().map(|a| a.to_string()));
+        let Ok(Command::Serve(config)) = Command::parse(all, |_| None) else {
+            panic!("bad test settings {:?}", args);
+        };
+        let flow = SurveyFlow::parse(include_str!("../flow.toml")).unwrap();
+        let labels = LabelRegistry::builtin();
+        AppState {
+            sessions: Mutex::new(Box::new(MemoryStore::new())),
+            responses: open_response_store(&config, &labels).unwrap(),
+            flow,
+            labels,
+            admin: AdminAuth::new(Some(ADMIN_TOKEN.to_string()), None),
+            assets: Assets::Embedded,
+            lib_files: StaticFiles::new("lib", LIB_CACHE_CONTROL),
+            static_files: StaticFiles::new("static", STATIC_CACHE_CONTROL),
+            config,
+        }
+    }
+
+    fn cleanup(state: AppState) {
+        fs::remove_dir_all(&state.config.data_dir).unwrap();
+    }
+
+    fn request(raw: String) -> Request {
+        Request::read_from(&mut raw.as_bytes()).unwrap()
+    }
+
+    fn cookie_header(session: Option<&str>) -> String {
+        session.map(|id| format!("Cookie: session_id={}\r\n", id)).unwrap_or_default()
+    }
+
+    fn get(state: &AppState, target: &str, session: Option<&str>) -> Response {
+        route_request(&request(format!("GET {} HTTP/1.1\r\n{}\r\n", target, cookie_header(session))), state)
+    }
+
+    fn post(state: &AppState, target: &str, session: &str, form: &str) -> Response {
+        let raw = format!(
+            "POST {} HTTP/1.1\r\n{}Content-Type: application/x-www-form-urlencoded\r\nContent-Length: {}\r\n\r\n{}",
+            target,
+            cookie_header(Some(session)),
+            form.len(),
+            form
+        );
+        route_request(&request(raw), state)
+    }
+
+    /// An authenticated admin GET
+    fn admin_get(state: &AppState, target: &str) -> Response {
+        let raw = format!("GET {} HTTP/1.1\r\nAuthorization: Bearer {}\r\n\r\n", target, ADMIN_TOKEN);
+        route_request(&request(raw), state)
+    }
+
+    /// An authenticated admin POST
+    fn admin_post(state: &AppState, target: &str) -> Response {
+        let raw = format!("POST {} HTTP/1.1\r\nAuthorization: Bearer {}\r\nContent-Length: 0\r\n\r\n", target, ADMIN_TOKEN);
+        route_request(&request(raw), state)
+    }
+
+    /// The session ID a response's cookie hands out
+    fn issued_session(response: &Response) -> String {
+        let cookie = response.header("Set-Cookie").expect("a session cookie");
+        cookie.strip_prefix("session_id=").and_then(|c| c.split(';').next()).unwrap().to_string()
+    }
+
+    /// Every recorded response as (column, value) lookups
+    fn recorded(state: &AppState) -> Vec<HashMap<String, String>> {
+        let page = state.responses.query(&export::Query::all()).unwrap();
+        page.rows.into_iter().map(|row| page.columns.iter().cloned().zip(row).collect()).collect()
+    }
+
+    #[test]
+    fn generates_unguessable_well_formed_session_ids() {
+        let (a, b) = (generate_session_id().unwrap(), generate_session_id().unwrap());
+        assert_eq!(a.len(), SESSION_ID_PREFIX.len() + SESSION_ID_BYTES * 2);
+        assert!(a.starts_with(SESSION_ID_PREFIX));
+        assert!(is_valid_session_id(&a) && is_valid_session_id(&b));
+        assert_ne!(a, b);
+    }
+
+    #[test]
+    fn rejects_malformed_session_ids() {
+        let hex = "0123456789abcdef0123456789abcdef";
+        assert!(is_valid_session_id(&format!("session_{}", hex)));
+        for id in [
+            String::new(),
+            "session_".to_string(),
+            hex.to_string(),
+            format!("session_{}", &hex[1..]),
+            format!("session_{}0", hex),
+            format!("session_{}", hex.to_uppercase()),
+            format!("session_{}g", &hex[1..]),
+            format!("session_{}", "0".repeat(10_000)),
+            format!("admin_{}", hex),
+            format!("session_{}; admin=1", hex),
+        ] {
+            assert!(!is_valid_session_id(&id), "{}", id);
+        }
+
+        let forged = request("GET / HTTP/1.1\r\nCookie: a=1; session_id=../etc; session_id=session_0123456789abcdef0123456789abcdef\r\n\r\n".to_string());
+        assert_eq!(extract_session_id(&forged).as_deref(), Some("session_0123456789abcdef0123456789abcdef"));
+        let malformed = request("GET / HTTP/1.1\r\nCookie: session_id=session_XYZ\r\n\r\n".to_string());
+        assert_eq!(extract_session_id(&malformed), None);
+    }
+
+    #[test]
+    fn does_not_adopt_unknown_session_ids() {
+        let state = test_state("adopt", &[]);
+        let forged = "session_0123456789abcdef0123456789abcdef";
+        let response = get(&state, "/", Some(forged));
+        assert_eq!(response.status, "200 OK");
+        let issued = issued_session(&response);
+        assert_ne!(issued, forged);
+        assert!(is_valid_session_id(&issued));
+        {
+            let sessions = state.sessions.lock().unwrap();
+            assert!(sessions.get(forged).is_none());
+            assert!(sessions.get(&issued).is_some());
+        }
+        // The issued ID is then recognised without a new cookie
+        assert!(get(&state, "/", Some(&issued)).header("Set-Cookie").is_none());
+        cleanup(state);
+    }
+
+    #[test]
+    fn reaper_records_sessions_abandoned_midway() {
+        let state = test_state("reap", &["--session-ttl-secs", "60"]);
+        {
+            let mut sessions = state.sessions.lock().unwrap();
+            sessions.press_button("midway", &state.flow, "start").unwrap();
+            sessions.get_or_create("untouched", &state.flow, DeviceClass::Desktop);
+        }
+        reap_idle_sessions(&state, SystemTime::now());
+        assert_eq!(state.sessions.lock().unwrap().iter().count(), 2);
+        assert!(recorded(&state).is_empty());
+
+        reap_idle_sessions(&state, SystemTime::now() + Duration::from_secs(120));
+        assert_eq!(state.sessions.lock().unwrap().iter().count(), 0);
+        let rows = recorded(&state);
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows[0]["session_id"], "midway");
+        assert_eq!(rows[0]["status"], "abandoned");
+        assert_eq!(rows[0]["last_page"], "2");
+        cleanup(state);
+    }
+
+    #[test]
+    fn restart_records_the_partial_session() {
+        let state = test_state("restart", &[]);
+        let id = issued_session(&get(&state, "/", None));
+        post(&state, "/page1", &id, "button=start");
+        let before = SystemTime::now() - Duration::from_secs(1);
+        assert_eq!(post(&state, "/page2", &id, "restart=true").header("Location"), Some("/page1"));
+
+        let rows = recorded(&state);
+        assert_eq!(rows.len(), 1);
+        assert_eq!((rows[0]["session_id"].as_str(), rows[0]["status"].as_str()), (id.as_str(), "restarted"));
+        assert_eq!(rows[0]["last_page"], "2");
+        let recorded_at = timestamp::parse_rfc3339(&rows[0]["recorded_at"]).unwrap();
+        assert!(recorded_at >= before && recorded_at <= SystemTime::now());
+        assert!(state.sessions.lock().unwrap().get(&id).unwrap().button_presses().is_empty());
+
+        // Restarting a fresh session has nothing to record
+        post(&state, "/page1", &id, "restart=true");
+        assert_eq!(recorded(&state).len(), 1);
+        cleanup(state);
+    }
+
+    #[test]
+    fn abandoned_rows_follow_the_log_abandoned_setting() {
+        for (setting, expected) in [("true", 1), ("false", 0)] {
+            let state = test_state(&format!("log-abandoned-{}", setting), &["--log-abandoned-sessions", setting]);
+            let id = issued_session(&get(&state, "/", None));
+            post(&state, "/page1", &id, "button=start");
+            reap_idle_sessions(&state, SystemTime::now() + state.config.session_ttl * 2);
+            assert!(state.sessions.lock().unwrap().get(&id).is_none());
+            assert_eq!(recorded(&state).len(), expected, "{}", setting);
+            cleanup(state);
+        }
+    }
+
+    #[test]
+    fn restore_route_needs_a_snapshot_name() {
+        let state = test_state("restore-route", &[]);
+        let id = issued_session(&get(&state, "/", None));
+        post(&state, "/page1", &id, "button=start");
+        post(&state, "/page2", &id, "restart=true");
+        let name = state.responses.archive(&state.config.archive_dir()).unwrap();
+        assert!(recorded(&state).is_empty());
+
+        for target in [
+            "/admin/archive/restore?confirm=yes",
+            "/admin/archive//restore?confirm=yes",
+            "/admin/archive/?confirm=yes",
+            "/admin/archive/x?confirm=yes",
+        ] {
+            assert_eq!(admin_post(&state, target).status, "404 NOT FOUND", "{}", target);
+        }
+        assert_eq!(admin_post(&state, "/admin/archive/missing.csv/restore?confirm=yes").status, "404 NOT FOUND");
+        assert_eq!(admin_post(&state, &format!("/admin/archive/{}/restore", name)).status, "400 BAD REQUEST");
+        assert_eq!(admin_post(&state, &format!("/admin/archive/{}/restore?confirm=yes", name)).status, "200 OK");
+        assert_eq!(recorded(&state).len(), 1);
+        cleanup(state);
+    }
+
+    #[test]
+    fn answers_redirect_and_refreshing_records_once() {
+        let state = test_state("prg", &[]);
+        let id = issued_session(&get(&state, "/", None));
+        let response = post(&state, "/page1", &id, "button=start");
+        assert_eq!(response.status, "303 SEE OTHER");
+        assert_eq!(response.header("Location"), Some("/page2"));
+
+        let mut last = response;
+        for _ in 0..20 {
+            if state.sessions.lock().unwrap().get(&id).unwrap().current_page() == state.flow.terminal_page() {
+                break;
+            }
+            let page = state.sessions.lock().unwrap().get(&id).unwrap().current_page();
+            let button = state.flow.page(page).unwrap().buttons()[0].clone();
+            last = post(&state, &format!("/page{}", page), &id, &format!("button={}", button));
+        }
+        let terminal = format!("/page{}", state.flow.terminal_page());
+        assert_eq!(last.header("Location"), Some(terminal.as_str()));
+        assert_eq!(recorded(&state).len(), 1);
+        assert_eq!(recorded(&state)[0]["status"], "completed");
+
+        // Refreshing the result page neither records again nor resets the session
+        for _ in 0..3 {
+            assert_eq!(get(&state, &terminal, Some(&id)).status, "200 OK");
+        }
+        assert_eq!(recorded(&state).len(), 1);
+        assert_eq!(state.sessions.lock().unwrap().get(&id).unwrap().current_page(), state.flow.terminal_page());
+        cleanup(state);
+    }
+
+    #[test]
+    fn restart_is_not_allowed_over_get() {
+        let state = test_state("get-restart", &[]);
+        let id = issued_session(&get(&state, "/", None));
+        post(&state, "/page1", &id, "button=start");
+        for target in ["/?restart=true", "/page2?restart=true", "/page2?x=1&restart=false"] {
+            let response = get(&state, target, Some(&id));
+            assert_eq!(response.status, "405 METHOD NOT ALLOWED", "{}", target);
+            assert_eq!(response.header("Allow"), Some("POST"));
+        }
+        assert_eq!(state.sessions.lock().unwrap().get(&id).unwrap().current_page(), 2);
+        assert!(recorded(&state).is_empty());
+        cleanup(state);
+    }
+
+    /// Serves one connection carrying `raw` and returns everything the
+    /// server wrote before closing it, and how long that took
+    fn exchange(state: &AppState, raw: &str, idle_timeout: Duration) -> (String, Duration) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        thread::scope(|scope| {
+            scope.spawn(|| {
+                let (stream, _) = listener.accept().unwrap();
+                serve_connection(stream, state, Duration::from_secs(5), idle_timeout);
+            });
+            let mut client = TcpStream::connect(address).unwrap();
+            client.set_read_timeout(Some(Duration::from_secs(10))).unwrap();
+            let started = Instant::now();
+            client.write_all(raw.as_bytes()).unwrap();
+            let mut received = Vec::new();
+            client.read_to_end(&mut received).unwrap();
+            (String::from_utf8_lossy(&received).into_owned(), started.elapsed())
+        })
+    }
+
+    #[test]
+    fn answers_pipelined_requests_in_order() {
+        let state = test_state("pipelined", &[]);
+        let raw = "GET /missing HTTP/1.1\r\nHost: x\r\n\r\nGET / HTTP/1.1\r\nHost: x\r\n\r\n";
+        let (received, _) = exchange(&state, raw, Duration::from_millis(200));
+        let statuses: Vec<&str> = received.lines().filter(|line| line.starts_with("HTTP/1.1 ")).collect();
+        assert_eq!(statuses, ["HTTP/1.1 404 NOT FOUND", "HTTP/1.1 200 OK"]);
+        assert!(received.contains("Connection: keep-alive"));
+        cleanup(state);
+    }
+
+    #[test]
+    fn connection_close_ends_the_connection() {
+        let state = test_state("close", &[]);
+        let raw = "GET / HTTP/1.1\r\nConnection: close\r\n\r\nGET /missing HTTP/1.1\r\n\r\n";
+        let (received, elapsed) = exchange(&state, raw, Duration::from_secs(30));
+        assert_eq!(received.matches("HTTP/1.1 ").count(), 1);
+        assert!(received.starts_with("HTTP/1.1 200 OK") && received.contains("Connection: close"));
+        // Closed straight away rather than after the idle timeout
+        assert!(elapsed < Duration::from_secs(5), "{:?}", elapsed);
+        cleanup(state);
+    }
+
+    #[test]
+    fn idle_keep_alive_connections_time_out() {
+        let state = test_state("idle", &[]);
+        let idle_timeout = Duration::from_millis(300);
+        let (received, elapsed) = exchange(&state, "GET / HTTP/1.1\r\n\r\n", idle_timeout);
+        assert_eq!(received.matches("HTTP/1.1 ").count(), 1);
+        assert!(received.contains("Connection: keep-alive"));
+        assert!(elapsed >= idle_timeout && elapsed < Duration::from_secs(5), "{:?}", elapsed);
+        cleanup(state);
+    }
+
+    #[test]
+    fn accept_loop_stops_and_in_flight_requests_finish() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        listener.set_nonblocking(true).unwrap();
+        let address = listener.local_addr().unwrap();
+        let (started_tx, started) = mpsc::channel();
+        let started_tx = Mutex::new(started_tx);
+        let pool = ThreadPool::new(1, 1, move |mut stream: TcpStream| {
+            started_tx.lock().unwrap().send(()).unwrap();
+            thread::sleep(Duration::from_millis(200));
+            stream.write_all(b"done").unwrap();
+        });
+        let stop = AtomicBool::new(false);
+
+        let mut client = TcpStream::connect(address).unwrap();
+        thread::scope(|scope| {
+            let accepting = scope.spawn(|| accept_connections(&listener, &pool, || stop.load(Ordering::SeqCst)));
+            started.recv().unwrap();
+            stop.store(true, Ordering::SeqCst);
+            accepting.join().unwrap();
+        });
+        drop(listener);
+        assert!(TcpStream::connect(address).is_err());
+
+        // The request was still sleeping when the loop stopped
+        pool.shutdown();
+        client.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        let mut received = String::new();
+        client.read_to_string(&mut received).unwrap();
+        assert_eq!(received, "done");
+    }
+
+    #[test]
+    fn out_of_range_export_dates_are_bad_requests() {
+        let state = test_state("export-range", &[]);
+        for target in ["/api/responses?to=292277026596-12-04", "/export.csv?from=99999999999999999-01-01"] {
+            assert_eq!(admin_get(&state, target).status, "400 BAD REQUEST", "{}", target);
+        }
+        assert_eq!(admin_get(&state, "/api/responses?to=9999-12-31").status, "200 OK");
+        cleanup(state);
     }
 }
@@ -0,0 +1,145 @@
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+
+/// A fixed set of worker threads fed by a bounded queue. Every submitted
+/// item is passed to the same handler.
+pub struct ThreadPool<T: Send + 'static> {
+    sender: Option<SyncSender<T>>,
+    workers: Vec<JoinHandle<()>>,
+}
+
+impl<T: Send + 'static> ThreadPool<T> {
+    /// Starts `size` workers sharing a queue that holds up to `queue_capacity` items
+    pub fn new<F>(size: usize, queue_capacity: usize, handler: F) -> Self
+    where
+        F: Fn(T) + Send + Sync + 'static,
+    {
+        assert!(size > 0, "thread pool needs at least one worker");
+        let (sender, receiver) = mpsc::sync_channel(queue_capacity);
+        let receiver = Arc::new(Mutex::new(receiver));
+        let handler = Arc::new(handler);
+        let workers = (0..size)
+            .map(|id| {
+                let receiver = Arc::clone(&receiver);
+                let handler = Arc::clone(&handler);
+                thread::Builder::new()
+                    .name(format!("worker-{}", id))
+                    .spawn(move || worker_loop(id, &receiver, &*handler))
+                    .expect("failed to spawn worker thread")
+            })
+            .collect();
+        Self { sender: Some(sender), workers }
+    }
+
+    /// Queues an item without blocking. Hands the item back if the queue is
+    /// full so the caller can turn the client away.
+    pub fn try_submit(&self, item: T) -> Result<(), T> {
+        let sender = self.sender.as_ref().expect("pool already shut down");
+        match sender.try_send(item) {
+            Ok(()) => Ok(()),
+            Err(TrySendError::Full(item)) | Err(TrySendError::Disconnected(item)) => Err(item),
+        }
+    }
+
+    /// Stops accepting work, lets the workers drain everything already
+    /// queued, and waits for them to finish
+    pub fn shutdown(mut self) {
+        self.join_workers();
+    }
+
+    fn join_workers(&mut self) {
+        // Closing the channel makes each worker exit once the queue is empty
+        drop(self.sender.take());
+        for worker in self.workers.drain(..) {
+            if worker.join().is_err() {
+                eprintln!("[ERROR] Worker thread panicked during shutdown");
+            }
+        }
+    }
+}
+
+impl<T: Send + 'static> Drop for ThreadPool<T> {
+    fn drop(&mut self) {
+        self.join_workers();
+    }
+}
+
+fn worker_loop<T>(id: usize, receiver: &Mutex<Receiver<T>>, handler: &(dyn Fn(T) + Send + Sync)) {
+    loop {
+        // Hold the lock only while waiting for the next item
+        let item = match receiver.lock().unwrap().recv() {
+            Ok(item) => item,
+            Err(_) => break,
+        };
+        // A panicking request must not take the worker down with it
+        if panic::catch_unwind(AssertUnwindSafe(|| handler(item))).is_err() {
+            eprintln!("[ERROR] Worker {} recovered from a panic while handling a request", id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    /// A pool whose handler records each item after running `before`
+    fn recording_pool(
+        size: usize,
+        queue_capacity: usize,
+        before: impl Fn(usize) + Send + Sync + 'static,
+    ) -> (ThreadPool<usize>, Arc<Mutex<Vec<usize>>>) {
+        let handled = Arc::new(Mutex::new(Vec::new()));
+        let sink = Arc::clone(&handled);
+        let pool = ThreadPool::new(size, queue_capacity, move |item| {
+            before(item);
+            sink.lock().unwrap().push(item);
+        });
+        (pool, handled)
+    }
+
+    #[test]
+    fn hands_items_back_when_the_queue_is_full() {
+        let (started_tx, started) = mpsc::channel();
+        let (release, gate) = mpsc::channel::<()>();
+        let (started_tx, gate) = (Mutex::new(started_tx), Mutex::new(gate));
+        let (pool, handled) = recording_pool(1, 1, move |_| {
+            started_tx.lock().unwrap().send(()).unwrap();
+            gate.lock().unwrap().recv().unwrap();
+        });
+        assert_eq!(pool.try_submit(0), Ok(()));
+        // The worker is busy with 0, so 1 fills the queue
+        started.recv().unwrap();
+        assert_eq!(pool.try_submit(1), Ok(()));
+        assert_eq!(pool.try_submit(2), Err(2));
+
+        release.send(()).unwrap();
+        release.send(()).unwrap();
+        pool.shutdown();
+        assert_eq!(*handled.lock().unwrap(), [0, 1]);
+    }
+
+    #[test]
+    fn workers_survive_a_panicking_job() {
+        let (pool, handled) = recording_pool(1, 4, |item| assert_ne!(item, 0, "job 0 panics"));
+        for item in 0..3 {
+            pool.try_submit(item).unwrap();
+        }
+        pool.shutdown();
+        assert_eq!(*handled.lock().unwrap(), [1, 2]);
+    }
+
+    #[test]
+    fn shutdown_drains_queued_jobs() {
+        let (pool, handled) = recording_pool(2, 8, |_| thread::sleep(Duration::from_millis(10)));
+        for item in 0..8 {
+            pool.try_submit(item).unwrap();
+        }
+        pool.shutdown();
+        let mut handled = handled.lock().unwrap().clone();
+        handled.sort();
+        assert_eq!(handled, (0..8).collect::<Vec<_>>());
+    }
+}
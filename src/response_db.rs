@@ -0,0 +1,461 @@
+//! An embedded response database: one append-only file of checksummed
+//! records, with indexes kept in memory and rebuilt when it is opened.
+//!
+//! The file starts with `HPOMDB1\n`. Each record is a kind byte (`S` for a
+//! schema, `R` for a response), the payload length and its FNV-1a checksum
+//! (both little-endian `u32`), then the payload: one CSV record. A response
+//! uses the column names of the latest schema before it, so a schema change
+//! only appends a new schema record; reads map every row onto the current
+//! columns by name.
+//!
+//! Appends hold an exclusive `File::lock`, and every operation first indexes
+//! whatever other processes appended since, so several server processes on
+//! one disk can share a file. A record torn by a crash at the end of the file
+//! is ignored and overwritten by the next append. Archiving and restoring
+//! replace the file, so they assume one process.
+
+use crate::archive;
+use crate::csv;
+use crate::export::{Page, Query};
+use crate::response_store::ResponseStore;
+use crate::schema;
+use crate::timestamp;
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::ops::Bound;
+use std::os::unix::fs::FileExt;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+const MAGIC: &[u8] = b"HPOMDB1\n";
+
+/// Kind byte, length and checksum
+const RECORD_HEADER_LEN: u64 = 9;
+
+const SCHEMA: u8 = b'S';
+const ROW: u8 = b'R';
+
+/// Columns with an index for the exports' answer filters
+const INDEXED_COLUMNS: [&str; 2] = ["role", "question_type"];
+
+const RECORDED_AT_COLUMN: &str = "recorded_at";
+
+pub struct DbStore {
+    path: PathBuf,
+    columns: Vec<String>,
+    db: Mutex<Db>,
+}
+
+/// Where a response's payload is and which schema it uses
+struct RowEntry {
+    offset: u64,
+    len: usize,
+    schema: usize,
+}
+
+/// The open file and its indexes
+struct Db {
+    file: File,
+    /// Bytes indexed so far, always at a record boundary
+    indexed_len: u64,
+    schemas: Vec<Vec<String>>,
+    /// Indexed by row ID, which is also the export cursor
+    rows: Vec<RowEntry>,
+    /// Row IDs per (indexed column, answer), ascending
+    by_answer: HashMap<(&'static str, String), Vec<usize>>,
+    /// Row IDs per `recorded_at`
+    by_time: BTreeMap<SystemTime, Vec<usize>>,
+}
+
+impl DbStore {
+    /// Opens the database at `path`, creating it (and its directory) if needed
+    pub fn open(path: &Path, columns: &[&str]) -> io::Result<Self> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let db = Db::open(path)?;
+        println!("[DEBUG] Indexed {} response(s) in {}", db.rows.len(), path.display());
+        Ok(Self { path: path.to_path_buf(), columns: columns.iter().map(|c| c.to_string()).collect(), db: Mutex::new(db) })
+    }
+
+    /// Responses stored so far
+    pub fn row_count(&self) -> io::Result<usize> {
+        let mut db = self.db.lock().unwrap();
+        db.catch_up()?;
+        Ok(db.rows.len())
+    }
+
+    /// Appends every row of `page` under its own columns. Returns how many
+    /// rows were added.
+    pub fn import(&self, page: &Page) -> io::Result<usize> {
+        self.db.lock().unwrap().append(&page.columns, &page.rows)?;
+        Ok(page.rows.len())
+    }
+
+    /// Snapshots every row to `archive_dir` and empties the database. The
+    /// caller holds the lock, so no append lands between the two.
+    fn archive_locked(&self, db: &mut Db, archive_dir: &Path) -> io::Result<String> {
+        db.catch_up()?;
+        let page = db.select(&self.columns, &Query::all())?;
+        let name = archive::write_snapshot(archive_dir, &schema::write(&page))?;
+        self.reset(db)?;
+        Ok(name)
+    }
+
+    /// Replaces the file with an empty one
+    fn reset(&self, db: &mut Db) -> io::Result<()> {
+        let staging = self.path.with_extension("db.resetting");
+        let mut file = File::create(&staging)?;
+        file.write_all(MAGIC)?;
+        file.write_all(&encode(SCHEMA, &self.columns))?;
+        file.sync_all()?;
+        fs::rename(&staging, &self.path)?;
+        *db = Db::open(&self.path)?;
+        Ok(())
+    }
+}
+
+impl ResponseStore for DbStore {
+    fn describe(&self) -> String {
+        format!("database {}", self.path.display())
+    }
+
+    fn append(&self, row: &[String]) -> io::Result<()> {
+        self.db.lock().unwrap().append(&self.columns, std::slice::from_ref(&row.to_vec()))
+    }
+
+    fn query(&self, query: &Query) -> io::Result<Page> {
+        let mut db = self.db.lock().unwrap();
+        db.catch_up()?;
+        db.select(&self.columns, query)
+    }
+
+    fn archive(&self, archive_dir: &Path) -> io::Result<String> {
+        self.archive_locked(&mut self.db.lock().unwrap(), archive_dir)
+    }
+
+    fn restore(&self, archive_dir: &Path, name: &str) -> io::Result<Option<String>> {
+        let source = archive::snapshot_path(archive_dir, name)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("no snapshot named {}", name)))?;
+        let text = fs::read_to_string(source)?;
+        let snapshot = schema::read(&text, &self.columns)?.page;
+        // One guard from the count to the append, so a response recorded
+        // meanwhile is either archived or kept, never reset away
+        let mut db = self.db.lock().unwrap();
+        db.catch_up()?;
+        let archived = if db.rows.is_empty() {
+            self.reset(&mut db)?;
+            None
+        } else {
+            Some(self.archive_locked(&mut db, archive_dir)?)
+        };
+        db.append(&snapshot.columns, &snapshot.rows)?;
+        Ok(archived)
+    }
+}
+
+impl Db {
+    fn open(path: &Path) -> io::Result<Self> {
+        let file = OpenOptions::new().read(true).append(true).create(true).open(path)?;
+        file.lock()?;
+        let result = Self::write_magic_if_empty(&file);
+        file.unlock()?;
+        result?;
+
+        let mut magic = [0; MAGIC.len()];
+        file.read_exact_at(&mut magic, 0)?;
+        if magic != MAGIC {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!("{} is not a response database", path.display())));
+        }
+        let mut db = Db {
+            file,
+            indexed_len: MAGIC.len() as u64,
+            schemas: Vec::new(),
+            rows: Vec::new(),
+            by_answer: HashMap::new(),
+            by_time: BTreeMap::new(),
+        };
+        db.catch_up()?;
+        Ok(db)
+    }
+
+    fn write_magic_if_empty(mut file: &File) -> io::Result<()> {
+        if file.metadata()?.len() == 0 {
+            file.write_all(MAGIC)?;
+        }
+        Ok(())
+    }
+
+    /// Indexes records appended since the last call, by this process or
+    /// another. Stops before a record that isn't complete yet.
+    fn catch_up(&mut self) -> io::Result<()> {
+        let len = self.file.metadata()?.len();
+        while let Some((kind, payload)) = self.read_record(self.indexed_len, len)? {
+            let offset = self.indexed_len + RECORD_HEADER_LEN;
+            self.index(kind, offset, &payload)?;
+            self.indexed_len = offset + payload.len() as u64;
+        }
+        Ok(())
+    }
+
+    /// The record at `offset`, or `None` if it runs past `file_len`
+    fn read_record(&self, offset: u64, file_len: u64) -> io::Result<Option<(u8, Vec<u8>)>> {
+        if offset + RECORD_HEADER_LEN > file_len {
+            return Ok(None);
+        }
+        let mut header = [0; RECORD_HEADER_LEN as usize];
+        self.file.read_exact_at(&mut header, offset)?;
+        let len = u32::from_le_bytes(header[1..5].try_into().unwrap());
+        let sum = u32::from_le_bytes(header[5..9].try_into().unwrap());
+        if offset + RECORD_HEADER_LEN + u64::from(len) > file_len {
+            return Ok(None);
+        }
+        let mut payload = vec![0; len as usize];
+        self.file.read_exact_at(&mut payload, offset + RECORD_HEADER_LEN)?;
+        if checksum(&payload) != sum || !matches!(header[0], SCHEMA | ROW) {
+            return Err(corrupt(offset));
+        }
+        Ok(Some((header[0], payload)))
+    }
+
+    fn index(&mut self, kind: u8, offset: u64, payload: &[u8]) -> io::Result<()> {
+        let fields = decode(payload).ok_or_else(|| corrupt(offset))?;
+        if kind == SCHEMA {
+            self.schemas.push(fields);
+            return Ok(());
+        }
+        let schema = self.schemas.len().checked_sub(1).ok_or_else(|| corrupt(offset))?;
+        let id = self.rows.len();
+        self.rows.push(RowEntry { offset, len: payload.len(), schema });
+        let columns = &self.schemas[schema];
+        let field = |name: &str| columns.iter().position(|c| c == name).and_then(|i| fields.get(i)).filter(|v| !v.is_empty());
+        for column in INDEXED_COLUMNS {
+            if let Some(value) = field(column) {
+                self.by_answer.entry((column, value.clone())).or_default().push(id);
+            }
+        }
+        if let Some(recorded) = field(RECORDED_AT_COLUMN).and_then(|v| timestamp::parse_rfc3339(v)) {
+            self.by_time.entry(recorded).or_default().push(id);
+        }
+        Ok(())
+    }
+
+    /// Appends `rows` under `columns`, adding a schema record first if the
+    /// columns changed
+    fn append(&mut self, columns: &[String], rows: &[Vec<String>]) -> io::Result<()> {
+        self.file.lock()?;
+        let result = self.append_locked(columns, rows);
+        self.file.unlock()?;
+        result
+    }
+
+    fn append_locked(&mut self, columns: &[String], rows: &[Vec<String>]) -> io::Result<()> {
+        self.catch_up()?;
+        // Anything past the last whole record is a write torn by a crash
+        if self.file.metadata()?.len() > self.indexed_len {
+            eprintln!("[WARN] Discarding an incomplete record at the end of the response database");
+            self.file.set_len(self.indexed_len)?;
+        }
+        let mut bytes = Vec::new();
+        if self.schemas.last().is_none_or(|latest| latest != columns) {
+            bytes.extend(encode(SCHEMA, columns));
+        }
+        for row in rows {
+            bytes.extend(encode(ROW, row));
+        }
+        // One write, so readers in other processes never see a partial batch
+        (&self.file).write_all(&bytes)?;
+        self.catch_up()
+    }
+
+    /// Row IDs from `query.cursor()` on that can match, narrowed by the indexes
+    fn candidates(&self, query: &Query) -> Vec<usize> {
+        let mut ids: Option<Vec<usize>> = None;
+        for (column, label) in query.answers() {
+            if INDEXED_COLUMNS.contains(column) {
+                let list = self.by_answer.get(&(*column, label.clone())).map_or(&[][..], Vec::as_slice);
+                ids = Some(match ids {
+                    Some(ids) => ids.into_iter().filter(|id| list.binary_search(id).is_ok()).collect(),
+                    None => list.to_vec(),
+                });
+            }
+        }
+        if let (from, to) = query.range()
+            && (from.is_some() || to.is_some())
+        {
+            let from = from.map_or(Bound::Unbounded, Bound::Included);
+            let to = to.map_or(Bound::Unbounded, Bound::Excluded);
+            let mut in_range: Vec<usize> = self.by_time.range((from, to)).flat_map(|(_, ids)| ids.iter().copied()).collect();
+            in_range.sort_unstable();
+            ids = Some(match ids {
+                Some(ids) => ids.into_iter().filter(|id| in_range.binary_search(id).is_ok()).collect(),
+                None => in_range,
+            });
+        }
+        let mut ids = ids.unwrap_or_else(|| (0..self.rows.len()).collect());
+        ids.retain(|&id| id >= query.cursor());
+        ids
+    }
+
+    fn select(&self, columns: &[String], query: &Query) -> io::Result<Page> {
+        let column = |name: &str| columns.iter().position(|c| c == name);
+        let mut rows = Vec::new();
+        let mut next_cursor = None;
+        for id in self.candidates(query) {
+            let row = self.read_row(id, columns)?;
+            if !query.matches(column, &row) {
+                continue;
+            }
+            if query.limit().is_some_and(|limit| rows.len() == limit) {
+                next_cursor = Some(id);
+                break;
+            }
+            rows.push(row);
+        }
+        Ok(Page { columns: columns.to_vec(), rows, next_cursor })
+    }
+
+    /// A stored response, mapped onto `columns`
+    fn read_row(&self, id: usize, columns: &[String]) -> io::Result<Vec<String>> {
+        let entry = &self.rows[id];
+        let mut payload = vec![0; entry.len];
+        self.file.read_exact_at(&mut payload, entry.offset)?;
+        let fields = decode(&payload).ok_or_else(|| corrupt(entry.offset))?;
+        let schema = &self.schemas[entry.schema];
+        Ok(columns
+            .iter()
+            .map(|column| schema.iter().position(|c| c == column).and_then(|i| fields.get(i)).cloned().unwrap_or_default())
+            .collect())
+    }
+}
+
+fn encode(kind: u8, fields: &[String]) -> Vec<u8> {
+    let payload = csv::format_record(fields).into_bytes();
+    let mut record = Vec::with_capacity(RECORD_HEADER_LEN as usize + payload.len());
+    record.push(kind);
+    record.extend((payload.len() as u32).to_le_bytes());
+    record.extend(checksum(&payload).to_le_bytes());
+    record.extend(payload);
+    record
+}
+
+fn decode(payload: &[u8]) -> Option<Vec<String>> {
+    csv::Reader::new(std::str::from_utf8(payload).ok()?).next()?.ok()
+}
+
+/// 32-bit FNV-1a
+fn checksum(bytes: &[u8]) -> u32 {
+    bytes.iter().fold(0x811c_9dc5, |hash, &b| (hash ^ u32::from(b)).wrapping_mul(0x0100_0193))
+}
+
+fn corrupt(offset: u64) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("corrupt record at byte {} of the response database", offset))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::labels::LabelRegistry;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("hpom-db-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn row(id: &str, day: u32, role: &str, question_type: &str) -> Vec<String> {
+        [id, &format!("2026-01-{:02}T10:00:00Z", day), role, question_type].iter().map(|s| s.to_string()).collect()
+    }
+
+    const COLUMNS: [&str; 4] = ["session_id", "recorded_at", "role", "question_type"];
+
+    fn ids(store: &DbStore, query: &str) -> (Vec<String>, Option<usize>) {
+        let query = Query::parse(Some(query), &LabelRegistry::builtin(), None).unwrap();
+        let page = store.query(&query).unwrap();
+        (page.rows.into_iter().map(|row| row[0].clone()).collect(), page.next_cursor)
+    }
+
+    #[test]
+    fn answers_indexed_queries_like_the_csv() {
+        let dir = temp_dir("query");
+        let store = DbStore::open(&dir.join("responses.db"), &COLUMNS).unwrap();
+        store.append(&row("a", 1, "Engineer", "Multiple Choice")).unwrap();
+        store.append(&row("b", 2, "UX Designer", "True/False")).unwrap();
+        store.append(&row("c", 3, "Engineer", "True/False")).unwrap();
+        store.append(&row("d", 4, "Engineer", "Multiple Choice")).unwrap();
+
+        assert_eq!(ids(&store, "role=engi").0, ["a", "c", "d"]);
+        assert_eq!(ids(&store, "role=engi&question_type=tf").0, ["c"]);
+        assert_eq!(ids(&store, "from=2026-01-02&to=2026-01-03").0, ["b", "c"]);
+        assert_eq!(ids(&store, "role=engi&limit=2"), (vec!["a".to_string(), "c".to_string()], Some(3)));
+        assert_eq!(ids(&store, "role=engi&limit=2&cursor=3").0, ["d"]);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn reopens_across_schema_changes_and_torn_tails() {
+        let dir = temp_dir("reopen");
+        let path = dir.join("responses.db");
+        {
+            let store = DbStore::open(&path, &COLUMNS[..3]).unwrap();
+            store.append(&row("a", 1, "Engineer", "")[..3]).unwrap();
+        }
+        // A crash mid-append leaves half a record behind
+        OpenOptions::new().append(true).open(&path).unwrap().write_all(&encode(ROW, &row("x", 1, "", ""))[..12]).unwrap();
+
+        let store = DbStore::open(&path, &COLUMNS).unwrap();
+        store.append(&row("b", 2, "UX Designer", "True/False")).unwrap();
+        let page = store.query(&Query::all()).unwrap();
+        assert_eq!(page.rows, [row("a", 1, "Engineer", ""), row("b", 2, "UX Designer", "True/False")]);
+        assert_eq!(DbStore::open(&path, &COLUMNS).unwrap().row_count().unwrap(), 2);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn archives_and_restores_through_csv_snapshots() {
+        let dir = temp_dir("archive");
+        let store = DbStore::open(&dir.join("responses.db"), &COLUMNS).unwrap();
+        store.append(&row("a", 1, "Engineer", "Multiple Choice")).unwrap();
+        let name = store.archive(&dir.join("archive")).unwrap();
+        assert_eq!(store.row_count().unwrap(), 0);
+
+        store.append(&row("b", 2, "UX Designer", "True/False")).unwrap();
+        let archived = store.restore(&dir.join("archive"), &name).unwrap();
+        assert!(archived.is_some());
+        assert_eq!(ids(&store, "").0, ["a"]);
+        assert_eq!(ids(&store, "role=engi").0, ["a"]);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn restores_lose_no_concurrent_appends() {
+        let dir = temp_dir("restore-race");
+        let archive_dir = dir.join("archive");
+        let store = DbStore::open(&dir.join("responses.db"), &COLUMNS).unwrap();
+        store.append(&row("snap", 1, "Engineer", "")).unwrap();
+        let name = store.archive(&archive_dir).unwrap();
+
+        let archived = std::thread::scope(|scope| {
+            scope.spawn(|| {
+                for i in 0..200 {
+                    store.append(&row(&format!("w{}", i), 2, "", "")).unwrap();
+                }
+            });
+            (0..20).filter_map(|_| store.restore(&archive_dir, &name).unwrap()).collect::<Vec<_>>()
+        });
+
+        // Every appended row ends up either archived by a restore or still current
+        let mut written: Vec<String> = ids(&store, "").0;
+        for snapshot in &archived {
+            let text = fs::read_to_string(archive_dir.join(snapshot)).unwrap();
+            let columns: Vec<String> = COLUMNS.iter().map(|c| c.to_string()).collect();
+            written.extend(schema::read(&text, &columns).unwrap().page.rows.into_iter().map(|row| row[0].clone()));
+        }
+        written.retain(|id| id != "snap");
+        written.sort_by_key(|id| id[1..].parse::<usize>().unwrap());
+        assert_eq!(written, (0..200).map(|i| format!("w{}", i)).collect::<Vec<_>>());
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,206 @@
+use crate::archive;
+use crate::csv;
+use crate::export::{self, Page, Query};
+use crate::schema;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// Where recorded responses live. Rows are in `LabelRegistry::csv_columns`
+/// order; every reader (`/view-data`, `/results` and the exports) goes
+/// through `query`.
+pub trait ResponseStore: Send + Sync {
+    /// Where responses are kept, for logging
+    fn describe(&self) -> String;
+
+    /// Stores one response
+    fn append(&self, row: &[String]) -> io::Result<()>;
+
+    /// The responses `query` selects, in the order they were recorded
+    fn query(&self, query: &Query) -> io::Result<Page>;
+
+    /// Moves every response into a new archive snapshot and starts empty.
+    /// Returns the snapshot name.
+    fn archive(&self, archive_dir: &Path) -> io::Result<String>;
+
+    /// Replaces the responses with a snapshot's, archiving the current ones
+    /// first. Returns the name they were archived under, if there were any.
+    fn restore(&self, archive_dir: &Path, name: &str) -> io::Result<Option<String>>;
+}
+
+/// Responses appended to one flat CSV file, as they always were, under a
+/// `schema` version line
+pub struct CsvStore {
+    path: PathBuf,
+    columns: Vec<String>,
+    /// Held by every operation: archiving and restoring briefly leave no file
+    /// at `path`, which an append would otherwise recreate
+    file_lock: Mutex<()>,
+}
+
+impl CsvStore {
+    /// Creates the directory and the file (with its header) if needed, so an
+    /// unwritable data directory fails at startup rather than on every response.
+    /// A file from an older schema version is rewritten to the current
+    /// columns, after copying the original into `archive_dir`.
+    pub fn open(path: &Path, columns: &[&str], archive_dir: &Path) -> io::Result<Self> {
+        let store = Self {
+            path: path.to_path_buf(),
+            columns: columns.iter().map(|c| c.to_string()).collect(),
+            file_lock: Mutex::new(()),
+        };
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        store.write_header_if_empty(&file)?;
+        store.migrate(archive_dir)?;
+        Ok(store)
+    }
+
+    fn write_header_if_empty(&self, mut file: &File) -> io::Result<()> {
+        if file.metadata()?.len() == 0 {
+            file.write_all(schema::preamble(&self.columns).as_bytes())?;
+            println!("[DEBUG] Wrote header to {}", self.path.display());
+        }
+        Ok(())
+    }
+
+    /// Rewrites the file to the current schema version if it isn't already,
+    /// keeping a copy of an older original in `archive_dir`
+    fn migrate(&self, archive_dir: &Path) -> io::Result<()> {
+        let text = fs::read_to_string(&self.path)?;
+        let contents = schema::read(&text, &self.columns)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", self.path.display(), e)))?;
+        if contents.is_current() {
+            return Ok(());
+        }
+        if contents.version < schema::CURRENT_VERSION {
+            let name = archive::write_snapshot(archive_dir, &text)?;
+            println!("[DEBUG] Archived the version {} original of {} as {}", contents.version, self.path.display(), name);
+        }
+        // Write next to the file and rename over it so a crash leaves one or the other
+        let staging = self.path.with_extension("csv.migrating");
+        fs::write(&staging, schema::write(&contents.page))?;
+        fs::rename(&staging, &self.path)?;
+        println!(
+            "[WARN] Migrated {} response(s) in {} from schema version {} to {}",
+            contents.page.rows.len(),
+            self.path.display(),
+            contents.version,
+            schema::CURRENT_VERSION
+        );
+        Ok(())
+    }
+}
+
+impl ResponseStore for CsvStore {
+    fn describe(&self) -> String {
+        format!("CSV file {}", self.path.display())
+    }
+
+    fn append(&self, row: &[String]) -> io::Result<()> {
+        let _guard = self.file_lock.lock().unwrap();
+        let file = OpenOptions::new().create(true).append(true).open(&self.path)?;
+        self.write_header_if_empty(&file)?;
+        csv::Writer::new(&file).write_record(row)
+    }
+
+    fn query(&self, query: &Query) -> io::Result<Page> {
+        let text = {
+            let _guard = self.file_lock.lock().unwrap();
+            fs::read_to_string(&self.path)?
+        };
+        let body = schema::current_body(&text)?;
+        export::select(body, query).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))
+    }
+
+    fn archive(&self, archive_dir: &Path) -> io::Result<String> {
+        let _guard = self.file_lock.lock().unwrap();
+        let name = archive::archive_current(&self.path, archive_dir)?;
+        let mut file = OpenOptions::new().write(true).create_new(true).open(&self.path)?;
+        file.write_all(schema::preamble(&self.columns).as_bytes())?;
+        Ok(name)
+    }
+
+    fn restore(&self, archive_dir: &Path, name: &str) -> io::Result<Option<String>> {
+        let _guard = self.file_lock.lock().unwrap();
+        // Migrate the snapshot before it replaces anything, so one this build
+        // can't read leaves the current file alone
+        archive::restore(&self.path, archive_dir, name, |text| {
+            let contents = schema::read(text, &self.columns)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", name, e)))?;
+            Ok(schema::write(&contents.page))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn csv_store_appends_queries_and_archives() {
+        let dir = std::env::temp_dir().join(format!("hpom-csv-store-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let store = CsvStore::open(&dir.join("data.csv"), &["session_id", "role"], &dir.join("archive")).unwrap();
+        store.append(&["a".to_string(), "Engineer".to_string()]).unwrap();
+        store.append(&["b".to_string(), "UX, Designer".to_string()]).unwrap();
+
+        let page = store.query(&Query::all()).unwrap();
+        assert_eq!(page.columns, ["session_id", "role"]);
+        assert_eq!(page.rows[1], ["b", "UX, Designer"]);
+
+        let name = store.archive(&dir.join("archive")).unwrap();
+        assert!(store.query(&Query::all()).unwrap().rows.is_empty());
+        store.restore(&dir.join("archive"), &name).unwrap();
+        assert_eq!(store.query(&Query::all()).unwrap().rows.len(), 2);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn csv_store_migrates_old_files_on_open() {
+        let dir = std::env::temp_dir().join(format!("hpom-csv-migrate-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("data.csv");
+        let v1 = "session_id,role,question_type,team_size,role_pref,hpom_live,richard_cai,doc_string\na,Engineer,,,,,,x\n";
+        fs::write(&path, v1).unwrap();
+
+        let labels = crate::labels::LabelRegistry::builtin();
+        let columns = labels.csv_columns();
+        let store = CsvStore::open(&path, &columns, &dir.join("archive")).unwrap();
+        let page = store.query(&Query::all()).unwrap();
+        assert_eq!(page.columns, columns);
+        assert_eq!(page.rows[0][columns.iter().position(|c| *c == "role").unwrap()], "Engineer");
+        let archived = archive::list(&dir.join("archive")).unwrap();
+        assert_eq!(fs::read_to_string(dir.join("archive").join(&archived[0].name)).unwrap(), v1);
+
+        fs::write(&path, "#schema_version=99\nsession_id\n").unwrap();
+        let error = CsvStore::open(&path, &columns, &dir.join("archive")).err().unwrap();
+        assert!(error.to_string().contains("unknown schema version \"99\""), "{}", error);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn csv_store_keeps_current_data_when_a_snapshot_is_unreadable() {
+        let dir = std::env::temp_dir().join(format!("hpom-csv-bad-restore-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let archive_dir = dir.join("archive");
+        let store = CsvStore::open(&dir.join("data.csv"), &["session_id", "role"], &archive_dir).unwrap();
+        store.append(&["a".to_string(), "Engineer".to_string()]).unwrap();
+        let before = fs::read_to_string(dir.join("data.csv")).unwrap();
+
+        fs::create_dir_all(&archive_dir).unwrap();
+        for (name, text) in [("data-20260101T000000Z.csv", "#schema_version=99\nsession_id\n"), ("data-20260102T000000Z.csv", "id,answer\n")] {
+            fs::write(archive_dir.join(name), text).unwrap();
+            let error = store.restore(&archive_dir, name).unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+            assert_eq!(fs::read_to_string(dir.join("data.csv")).unwrap(), before);
+        }
+        assert_eq!(archive::list(&archive_dir).unwrap().len(), 2);
+        assert_eq!(store.query(&Query::all()).unwrap().rows.len(), 1);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,400 @@
+//! The `/results` dashboard: tallies the recorded responses and draws the charts
+//! as inline SVG, so the page needs no scripts or external files.
+
+use crate::export::Page;
+use crate::labels::LabelRegistry;
+use crate::template::html_escape;
+use std::fmt::Write;
+
+/// The question other answers are broken down by in the cross-tabs
+const BY_QUESTION: &str = "role";
+
+/// The question whose answer picks the survey branch
+const BRANCH_QUESTION: &str = "question_type";
+
+const COMPLETED: &str = "completed";
+
+/// Bar colours, reused in order
+const PALETTE: [&str; 6] = ["#2f6feb", "#f0883e", "#3fb950", "#a371f7", "#db61a2", "#8b949e"];
+
+/// Counts per label, in registry order followed by any labels the registry
+/// doesn't know (from older data)
+pub type Counts = Vec<(String, usize)>;
+
+/// Answer counts for one question
+#[derive(Debug)]
+pub struct QuestionTally {
+    pub id: &'static str,
+    pub prompt: &'static str,
+    pub counts: Counts,
+    pub unanswered: usize,
+}
+
+/// Answers to one question broken down by the answer to `BY_QUESTION`
+#[derive(Debug)]
+pub struct CrossTab {
+    pub prompt: &'static str,
+    pub rows: Vec<String>,
+    pub columns: Vec<String>,
+    /// `counts[row][column]`
+    pub counts: Vec<Vec<usize>>,
+}
+
+#[derive(Debug, Default)]
+pub struct Results {
+    pub responses: usize,
+    /// Responses per status, e.g. completed or abandoned
+    pub statuses: Counts,
+    /// Responses per device class, if the responses record it
+    pub devices: Counts,
+    pub questions: Vec<QuestionTally>,
+    pub cross_tabs: Vec<CrossTab>,
+}
+
+impl Results {
+    /// Tallies every response in `page`, finding columns by name
+    pub fn tally(page: &Page, labels: &LabelRegistry) -> Self {
+        let column = |name: &str| page.columns.iter().position(|h| h == name);
+        let status_column = column("status");
+        let device_column = column("device");
+        let by_column = column(BY_QUESTION);
+
+        let mut results = Results::default();
+        for question in labels.questions() {
+            let counts = labels.answers_to(question.id).map(|a| (a.label.to_string(), 0)).collect();
+            results.questions.push(QuestionTally { id: question.id, prompt: question.prompt, counts, unanswered: 0 });
+        }
+        let by_labels: Vec<String> = labels.answers_to(BY_QUESTION).map(|a| a.label.to_string()).collect();
+        for question in labels.questions().iter().filter(|q| q.id != BY_QUESTION) {
+            let columns: Vec<String> = labels.answers_to(question.id).map(|a| a.label.to_string()).collect();
+            results.cross_tabs.push(CrossTab {
+                prompt: question.prompt,
+                rows: by_labels.clone(),
+                counts: vec![vec![0; columns.len()]; by_labels.len()],
+                columns,
+            });
+        }
+
+        for fields in &page.rows {
+            let field = |index: Option<usize>| index.and_then(|i| fields.get(i)).map_or("", |f| f.as_str());
+            results.responses += 1;
+            let status = field(status_column);
+            increment(&mut results.statuses, if status.is_empty() { "unknown" } else { status });
+            if device_column.is_some() {
+                let device = field(device_column);
+                increment(&mut results.devices, if device.is_empty() { "unknown" } else { device });
+            }
+
+            let by = field(by_column);
+            let mut tabs = results.cross_tabs.iter_mut();
+            for tally in &mut results.questions {
+                let answer = field(column(tally.id));
+                if answer.is_empty() {
+                    tally.unanswered += 1;
+                } else {
+                    increment(&mut tally.counts, answer);
+                }
+                if tally.id == BY_QUESTION {
+                    continue;
+                }
+                let tab = tabs.next().expect("one cross-tab per other question");
+                if !by.is_empty() && !answer.is_empty() {
+                    tab.add(by, answer);
+                }
+            }
+        }
+        results
+    }
+
+    /// Responses that reached the end of the survey
+    pub fn completed(&self) -> usize {
+        self.statuses.iter().find(|(status, _)| status == COMPLETED).map_or(0, |(_, n)| *n)
+    }
+
+    /// The dashboard page
+    pub fn to_html(&self) -> String {
+        let mut html = String::from(
+            "<html><head><title>Survey Results</title><style>\
+             body{font-family:sans-serif;margin:2em;color:#1b2a4a;}section{margin-bottom:2.5em;}\
+             h3{margin-bottom:0.3em;}p.note{color:#57606a;margin-top:0;}\
+             </style></head><body><h2>Survey Results</h2>",
+        );
+        if self.responses == 0 {
+            html.push_str("<p>No responses yet.</p></body></html>");
+            return html;
+        }
+        write!(
+            html,
+            "<p>{} responses, {} completed ({} completion rate). <a href=\"/view-data\">Raw data</a></p>",
+            self.responses,
+            self.completed(),
+            percent(self.completed(), self.responses)
+        )
+        .unwrap();
+
+        html.push_str("<section><h3>Completion</h3>");
+        html.push_str(&stacked_bar(&self.statuses));
+        html.push_str("</section>");
+
+        if !self.devices.is_empty() {
+            html.push_str("<section><h3>Devices</h3>");
+            html.push_str("<p class=\"note\">Guessed from the User-Agent; bots are crawlers, link previews and scripts</p>");
+            html.push_str(&stacked_bar(&self.devices));
+            html.push_str("</section>");
+        }
+
+        if let Some(branch) = self.questions.iter().find(|q| q.id == BRANCH_QUESTION) {
+            html.push_str("<section><h3>Branch split</h3>");
+            write!(html, "<p class=\"note\">{}, across responses that got that far</p>", html_escape(branch.prompt)).unwrap();
+            html.push_str(&stacked_bar(&branch.counts));
+            html.push_str("</section>");
+        }
+
+        for tally in &self.questions {
+            let answered: usize = tally.counts.iter().map(|(_, n)| n).sum();
+            write!(html, "<section><h3>{}</h3>", html_escape(tally.prompt)).unwrap();
+            write!(html, "<p class=\"note\">{} answered, {} did not</p>", answered, tally.unanswered).unwrap();
+            html.push_str(&bar_chart(&tally.counts, answered));
+            html.push_str("</section>");
+        }
+
+        for tab in &self.cross_tabs {
+            write!(html, "<section><h3>{} by role</h3>", html_escape(tab.prompt)).unwrap();
+            html.push_str("<p class=\"note\">Percentages are per row</p>");
+            html.push_str(&heatmap(tab));
+            html.push_str("</section>");
+        }
+        html.push_str("</body></html>");
+        html
+    }
+}
+
+impl CrossTab {
+    /// Counts one response, adding a row or column for labels the registry
+    /// doesn't know
+    fn add(&mut self, row: &str, column: &str) {
+        let r = match self.rows.iter().position(|r| r == row) {
+            Some(r) => r,
+            None => {
+                self.rows.push(row.to_string());
+                self.counts.push(vec![0; self.columns.len()]);
+                self.rows.len() - 1
+            }
+        };
+        let c = match self.columns.iter().position(|c| c == column) {
+            Some(c) => c,
+            None => {
+                self.columns.push(column.to_string());
+                self.counts.iter_mut().for_each(|row| row.push(0));
+                self.columns.len() - 1
+            }
+        };
+        self.counts[r][c] += 1;
+    }
+}
+
+fn increment(counts: &mut Counts, label: &str) {
+    match counts.iter_mut().find(|(l, _)| l == label) {
+        Some((_, n)) => *n += 1,
+        None => counts.push((label.to_string(), 1)),
+    }
+}
+
+/// `n` as a rounded percentage of `total`
+fn percent(n: usize, total: usize) -> String {
+    format!("{}%", (n * 100 + total / 2).checked_div(total).unwrap_or(0))
+}
+
+const LABEL_WIDTH: usize = 200;
+const BAR_WIDTH: usize = 320;
+const ROW_HEIGHT: usize = 28;
+
+/// One horizontal bar per label, scaled to `total`
+fn bar_chart(counts: &Counts, total: usize) -> String {
+    let width = LABEL_WIDTH + BAR_WIDTH + 100;
+    let height = ROW_HEIGHT * counts.len().max(1);
+    let mut svg = format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{0}\" height=\"{1}\" viewBox=\"0 0 {0} {1}\" font-family=\"sans-serif\" font-size=\"13\">",
+        width, height
+    );
+    for (i, (label, n)) in counts.iter().enumerate() {
+        let y = i * ROW_HEIGHT;
+        let bar = (n * BAR_WIDTH).checked_div(total).unwrap_or(0);
+        let text_y = y + ROW_HEIGHT / 2 + 4;
+        write!(
+            svg,
+            "<text x=\"{}\" y=\"{}\" text-anchor=\"end\">{}</text>\
+             <rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\" fill=\"{}\"/>\
+             <text x=\"{}\" y=\"{}\">{} ({})</text>",
+            LABEL_WIDTH - 8,
+            text_y,
+            html_escape(label),
+            LABEL_WIDTH,
+            y + 4,
+            bar,
+            ROW_HEIGHT - 8,
+            PALETTE[0],
+            LABEL_WIDTH + bar + 6,
+            text_y,
+            n,
+            percent(*n, total)
+        )
+        .unwrap();
+    }
+    svg.push_str("</svg>");
+    svg
+}
+
+/// A single bar split into each label's share, with a legend underneath
+fn stacked_bar(counts: &Counts) -> String {
+    let total: usize = counts.iter().map(|(_, n)| n).sum();
+    let width = LABEL_WIDTH + BAR_WIDTH + 100;
+    let height = 36 + ROW_HEIGHT * counts.len();
+    let mut svg = format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{0}\" height=\"{1}\" viewBox=\"0 0 {0} {1}\" font-family=\"sans-serif\" font-size=\"13\">",
+        width, height
+    );
+    let mut x = 0;
+    for (i, (label, n)) in counts.iter().enumerate() {
+        // The last segment takes up any rounding slack so the bar is full
+        let segment = if total == 0 {
+            0
+        } else if i + 1 == counts.len() {
+            width - x
+        } else {
+            n * width / total
+        };
+        write!(
+            svg,
+            "<rect x=\"{}\" y=\"0\" width=\"{}\" height=\"24\" fill=\"{}\"><title>{}: {}</title></rect>",
+            x,
+            segment,
+            PALETTE[i % PALETTE.len()],
+            html_escape(label),
+            n
+        )
+        .unwrap();
+        x += segment;
+        let y = 36 + i * ROW_HEIGHT;
+        write!(
+            svg,
+            "<rect x=\"0\" y=\"{}\" width=\"14\" height=\"14\" fill=\"{}\"/><text x=\"22\" y=\"{}\">{}: {} ({})</text>",
+            y,
+            PALETTE[i % PALETTE.len()],
+            y + 12,
+            html_escape(label),
+            n,
+            percent(*n, total)
+        )
+        .unwrap();
+    }
+    svg.push_str("</svg>");
+    svg
+}
+
+const CELL_WIDTH: usize = 120;
+const CELL_HEIGHT: usize = 34;
+
+/// A grid of counts shaded by each cell's share of its row
+fn heatmap(tab: &CrossTab) -> String {
+    let width = LABEL_WIDTH + CELL_WIDTH * tab.columns.len();
+    let height = CELL_HEIGHT * (tab.rows.len() + 1);
+    let mut svg = format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{0}\" height=\"{1}\" viewBox=\"0 0 {0} {1}\" font-family=\"sans-serif\" font-size=\"12\">",
+        width, height
+    );
+    for (c, column) in tab.columns.iter().enumerate() {
+        write!(
+            svg,
+            "<text x=\"{}\" y=\"{}\" text-anchor=\"middle\" font-weight=\"bold\">{}</text>",
+            LABEL_WIDTH + c * CELL_WIDTH + CELL_WIDTH / 2,
+            CELL_HEIGHT / 2 + 4,
+            html_escape(column)
+        )
+        .unwrap();
+    }
+    for (r, (row, counts)) in tab.rows.iter().zip(&tab.counts).enumerate() {
+        let y = (r + 1) * CELL_HEIGHT;
+        let row_total: usize = counts.iter().sum();
+        write!(
+            svg,
+            "<text x=\"{}\" y=\"{}\" text-anchor=\"end\" font-weight=\"bold\">{}</text>",
+            LABEL_WIDTH - 8,
+            y + CELL_HEIGHT / 2 + 4,
+            html_escape(row)
+        )
+        .unwrap();
+        for (c, n) in counts.iter().enumerate() {
+            let x = LABEL_WIDTH + c * CELL_WIDTH;
+            let shade = if row_total == 0 { 0.0 } else { *n as f64 / row_total as f64 };
+            write!(
+                svg,
+                "<rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\" fill=\"{}\" fill-opacity=\"{:.2}\" stroke=\"#d0d7de\"/>\
+                 <text x=\"{}\" y=\"{}\" text-anchor=\"middle\">{} ({})</text>",
+                x,
+                y,
+                CELL_WIDTH,
+                CELL_HEIGHT,
+                PALETTE[0],
+                0.08 + shade * 0.8,
+                x + CELL_WIDTH / 2,
+                y + CELL_HEIGHT / 2 + 4,
+                n,
+                percent(*n, row_total)
+            )
+            .unwrap();
+        }
+    }
+    svg.push_str("</svg>");
+    svg
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::export::{self, Query};
+
+    fn tally(csv: &str) -> Results {
+        Results::tally(&export::select(csv, &Query::all()).unwrap(), &LabelRegistry::builtin())
+    }
+
+    const CSV: &str = "session_id,status,device,role,question_type,team_size,role_pref,hpom_live,richard_cai,doc_string\n\
+                       a,completed,desktop,Engineer,Multiple Choice,6-8 people,Engineer,,,x\n\
+                       b,completed,mobile,Engineer,True/False,,,True,False,x\n\
+                       c,abandoned,desktop,UX Designer,Multiple Choice,,,,,x\n\
+                       d,abandoned,bot,,,,,,,x\n";
+
+    #[test]
+    fn tallies_answers_statuses_and_cross_tabs() {
+        let results = tally(CSV);
+        assert_eq!(results.responses, 4);
+        assert_eq!(results.completed(), 2);
+        assert_eq!(percent(results.completed(), results.responses), "50%");
+        assert_eq!(results.devices[0], ("desktop".to_string(), 2));
+
+        let branch = results.questions.iter().find(|q| q.id == "question_type").unwrap();
+        assert_eq!(branch.counts, vec![("Multiple Choice".to_string(), 2), ("True/False".to_string(), 1)]);
+        assert_eq!(branch.unanswered, 1);
+
+        let by_role = results.cross_tabs.iter().find(|t| t.prompt == "Question type").unwrap();
+        let engineer = by_role.rows.iter().position(|r| r == "Engineer").unwrap();
+        assert_eq!(by_role.counts[engineer], vec![1, 1]);
+    }
+
+    #[test]
+    fn keeps_labels_the_registry_does_not_know() {
+        let csv = "session_id,status,role\na,completed,Intern\n";
+        let results = tally(csv);
+        let role = results.questions.iter().find(|q| q.id == "role").unwrap();
+        assert_eq!(role.counts.last(), Some(&("Intern".to_string(), 1)));
+    }
+
+    #[test]
+    fn renders_escaped_inline_svg() {
+        let csv = "session_id,status,role\na,<script>,Engineer\n";
+        let html = tally(csv).to_html();
+        assert!(html.contains("<svg"));
+        assert!(!html.contains("<script>"));
+        assert!(html.contains("0% completion rate"));
+    }
+}
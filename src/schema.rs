@@ -0,0 +1,197 @@
+//! Versions of the response CSV's column set.
+//!
+//! Files start with a `#schema_version=N` line above the header. Files from
+//! before that line existed are recognised by their header. Rows of older
+//! versions are mapped onto the current columns by name, so a new column is
+//! empty in old rows instead of shifting their answers.
+
+use crate::csv::{self, CsvError};
+use crate::export::Page;
+use std::fmt;
+use std::io;
+
+const VERSION_PREFIX: &str = "#schema_version=";
+
+/// Bump this and add the new column set to `VERSIONS` whenever
+/// `LabelRegistry::csv_columns` changes
+pub const CURRENT_VERSION: u32 = 4;
+
+/// The columns of each version, oldest first
+const VERSIONS: [&[&str]; CURRENT_VERSION as usize] = [
+    &["session_id", "role", "question_type", "team_size", "role_pref", "hpom_live", "richard_cai", "doc_string"],
+    // Whether the session completed, restarted or was abandoned
+    &["session_id", "status", "role", "question_type", "team_size", "role_pref", "hpom_live", "richard_cai", "doc_string"],
+    &[
+        "session_id", "status", "last_page", "recorded_at", "role", "question_type", "team_size", "role_pref", "hpom_live",
+        "richard_cai", "doc_string",
+    ],
+    &[
+        "session_id", "status", "last_page", "recorded_at", "started_at", "finished_at", "device", "page_dwell_secs", "role",
+        "question_type", "team_size", "role_pref", "hpom_live", "richard_cai", "doc_string",
+    ],
+];
+
+#[derive(Debug, PartialEq)]
+pub enum SchemaError {
+    /// A version line this build can't read, e.g. from a newer release
+    UnknownVersion(String),
+    /// No version line, and a header that no version had
+    UnknownHeader(Vec<String>),
+    /// The file is readable but not at the current version yet
+    NotMigrated(u32),
+    Csv(CsvError),
+}
+
+impl fmt::Display for SchemaError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SchemaError::UnknownVersion(version) => {
+                write!(f, "unknown schema version {:?} (this build reads versions 1 to {})", version, CURRENT_VERSION)
+            }
+            SchemaError::UnknownHeader(header) => {
+                write!(f, "no schema version line and the header matches no known version: {}", header.join(","))
+            }
+            SchemaError::NotMigrated(version) => {
+                write!(f, "schema version {} has not been migrated to version {}; restart the server", version, CURRENT_VERSION)
+            }
+            SchemaError::Csv(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl From<SchemaError> for io::Error {
+    fn from(e: SchemaError) -> Self {
+        io::Error::new(io::ErrorKind::InvalidData, e.to_string())
+    }
+}
+
+/// A response CSV read at whatever version it was written
+#[derive(Debug)]
+pub struct Contents {
+    pub version: u32,
+    /// Whether the file has a version line
+    pub declared: bool,
+    /// Every row, mapped onto the current columns
+    pub page: Page,
+}
+
+impl Contents {
+    /// Whether the file needs rewriting to be current
+    pub fn is_current(&self) -> bool {
+        self.declared && self.version == CURRENT_VERSION
+    }
+}
+
+/// The version line and header that start a current file
+pub fn preamble(columns: &[String]) -> String {
+    format!("{}{}\n{}", VERSION_PREFIX, CURRENT_VERSION, csv::format_record(columns))
+}
+
+/// `page` as a current file
+pub fn write(page: &Page) -> String {
+    let mut text = preamble(&page.columns);
+    for row in &page.rows {
+        text.push_str(&csv::format_record(row));
+    }
+    text
+}
+
+/// The CSV after the version line, if the file is at the current version
+pub fn current_body(text: &str) -> Result<&str, SchemaError> {
+    match split_version(text)? {
+        (Some(CURRENT_VERSION), body) => Ok(body),
+        // An empty file gets its version line with the header on the first append
+        (None, "") => Ok(""),
+        (Some(version), _) => Err(SchemaError::NotMigrated(version)),
+        (None, body) => Err(SchemaError::NotMigrated(detect(body)?)),
+    }
+}
+
+/// Reads a file of any known version as rows of `columns`
+pub fn read(text: &str, columns: &[String]) -> Result<Contents, SchemaError> {
+    let (declared, body) = split_version(text)?;
+    let version = match declared {
+        Some(version) => version,
+        None => detect(body)?,
+    };
+    let mut records = csv::Reader::new(body);
+    let header = records.next().transpose().map_err(SchemaError::Csv)?.unwrap_or_default();
+    let sources: Vec<Option<usize>> = columns.iter().map(|c| header.iter().position(|h| h == c)).collect();
+    let mut rows = Vec::new();
+    for record in records {
+        let fields = record.map_err(SchemaError::Csv)?;
+        rows.push(sources.iter().map(|source| source.and_then(|i| fields.get(i)).cloned().unwrap_or_default()).collect());
+    }
+    Ok(Contents {
+        version,
+        declared: declared.is_some(),
+        page: Page { columns: columns.to_vec(), rows, next_cursor: None },
+    })
+}
+
+/// The declared version, if there's a version line, and the text after it
+fn split_version(text: &str) -> Result<(Option<u32>, &str), SchemaError> {
+    let Some(rest) = text.strip_prefix(VERSION_PREFIX) else {
+        return Ok((None, text));
+    };
+    let (line, body) = rest.split_once('\n').unwrap_or((rest, ""));
+    let line = line.trim_end_matches('\r');
+    match line.parse() {
+        Ok(version) if (1..=CURRENT_VERSION).contains(&version) => Ok((Some(version), body)),
+        _ => Err(SchemaError::UnknownVersion(line.to_string())),
+    }
+}
+
+/// The version of an unversioned file, from its header
+fn detect(body: &str) -> Result<u32, SchemaError> {
+    let header = csv::Reader::new(body).next().transpose().map_err(SchemaError::Csv)?.unwrap_or_default();
+    if header.is_empty() {
+        return Ok(CURRENT_VERSION);
+    }
+    VERSIONS
+        .iter()
+        .position(|columns| *columns == header.as_slice())
+        .map(|i| i as u32 + 1)
+        .ok_or(SchemaError::UnknownHeader(header))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::labels::LabelRegistry;
+
+    fn current() -> Vec<String> {
+        LabelRegistry::builtin().csv_columns().iter().map(|c| c.to_string()).collect()
+    }
+
+    #[test]
+    fn current_version_matches_the_registry() {
+        assert_eq!(VERSIONS.last().unwrap().to_vec(), LabelRegistry::builtin().csv_columns());
+    }
+
+    #[test]
+    fn migrates_unversioned_files_by_column_name() {
+        let v2 = "session_id,status,role,question_type,team_size,role_pref,hpom_live,richard_cai,doc_string\n\
+                  a,completed,Engineer,True/False,,,True,False,x\n";
+        let contents = read(v2, &current()).unwrap();
+        assert_eq!((contents.version, contents.declared, contents.is_current()), (2, false, false));
+        let row = &contents.page.rows[0];
+        assert_eq!(row.len(), current().len());
+        assert_eq!(row[current().iter().position(|c| c == "role").unwrap()], "Engineer");
+        assert_eq!(row[current().iter().position(|c| c == "device").unwrap()], "");
+
+        let rewritten = write(&contents.page);
+        assert!(rewritten.starts_with("#schema_version=4\nsession_id,status,last_page,"));
+        assert!(read(&rewritten, &current()).unwrap().is_current());
+        assert_eq!(current_body(v2), Err(SchemaError::NotMigrated(2)));
+    }
+
+    #[test]
+    fn rejects_unknown_versions_and_headers() {
+        for text in ["#schema_version=5\nsession_id\n", "#schema_version=0\n", "#schema_version=two\n"] {
+            assert!(matches!(read(text, &current()), Err(SchemaError::UnknownVersion(_))), "{}", text);
+        }
+        assert!(matches!(read("id,answer\n", &current()), Err(SchemaError::UnknownHeader(_))));
+        assert_eq!(current_body("#schema_version=4\nsession_id\n"), Ok("session_id\n"));
+    }
+}
@@ -0,0 +1,380 @@
+use crate::csv;
+use crate::device::DeviceClass;
+use crate::flow::SurveyFlow;
+use crate::user_session::{UserSession, ValidationResult};
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Where sessions live between requests. Every state change goes through
+/// these methods so a durable store can record it.
+pub trait SessionStore: Send {
+    fn get(&self, id: &str) -> Option<&UserSession>;
+
+    /// The session with this ID, starting a new one on `device` if there is none
+    fn get_or_create(&mut self, id: &str, flow: &SurveyFlow, device: DeviceClass) -> &UserSession;
+
+    /// Replaces the session with a fresh one on the start page, on the same device
+    fn reset(&mut self, id: &str, flow: &SurveyFlow);
+
+    /// Applies a button press, starting the session (on an unknown device)
+    /// first if needed
+    fn press_button(&mut self, id: &str, flow: &SurveyFlow, button: &str) -> ValidationResult<usize>;
+
+    /// Records that the user just interacted with the session
+    fn touch(&mut self, id: &str);
+
+    fn remove(&mut self, id: &str) -> Option<UserSession>;
+
+    fn iter(&self) -> Box<dyn Iterator<Item = (&String, &UserSession)> + '_>;
+
+    /// Drops history the store no longer needs. Called periodically.
+    fn compact(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Removes and returns every session idle for longer than `ttl`
+    fn remove_idle(&mut self, now: SystemTime, ttl: Duration) -> Vec<(String, UserSession)> {
+        let stale: Vec<String> = self
+            .iter()
+            .filter(|(_, session)| session.idle_time(now) > ttl)
+            .map(|(id, _)| id.clone())
+            .collect();
+        stale.into_iter().filter_map(|id| self.remove(&id).map(|session| (id, session))).collect()
+    }
+}
+
+/// Sessions kept only in memory; they are lost on restart
+#[derive(Debug, Default)]
+pub struct MemoryStore {
+    sessions: HashMap<String, UserSession>,
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl SessionStore for MemoryStore {
+    fn get(&self, id: &str) -> Option<&UserSession> {
+        self.sessions.get(id)
+    }
+
+    fn get_or_create(&mut self, id: &str, flow: &SurveyFlow, device: DeviceClass) -> &UserSession {
+        self.sessions
+            .entry(id.to_string())
+            .or_insert_with(|| UserSession::started(flow.start_page(), device, SystemTime::now()))
+    }
+
+    fn reset(&mut self, id: &str, flow: &SurveyFlow) {
+        let device = self.sessions.get(id).map_or(DeviceClass::Unknown, UserSession::device);
+        self.sessions.insert(id.to_string(), UserSession::started(flow.start_page(), device, SystemTime::now()));
+    }
+
+    fn press_button(&mut self, id: &str, flow: &SurveyFlow, button: &str) -> ValidationResult<usize> {
+        self.sessions
+            .entry(id.to_string())
+            .or_insert_with(|| UserSession::new(flow.start_page()))
+            .process_button_press(flow, button)
+    }
+
+    fn touch(&mut self, id: &str) {
+        if let Some(session) = self.sessions.get_mut(id) {
+            session.touch();
+        }
+    }
+
+    fn remove(&mut self, id: &str) -> Option<UserSession> {
+        self.sessions.remove(id)
+    }
+
+    fn iter(&self) -> Box<dyn Iterator<Item = (&String, &UserSession)> + '_> {
+        Box::new(self.sessions.iter())
+    }
+}
+
+/// Journal entries beyond which compaction is considered
+const COMPACT_MIN_ENTRIES: usize = 1000;
+
+/// Sessions kept in memory and mirrored to an append-only journal, so they
+/// survive restarts. Each line is a CSV record `unix_time,op,session_id,arg`
+/// where `unix_time` is seconds with millisecond decimals and `op` is `new`
+/// (with the device class as `arg`), `press` (with the button) or `remove`.
+///
+/// `touch` is not journaled, so after a restart a session's idle time
+/// counts from its last journaled change.
+pub struct FileStore {
+    memory: MemoryStore,
+    path: PathBuf,
+    journal: File,
+    entries: usize,
+}
+
+impl FileStore {
+    /// Opens the journal at `path`, rebuilding sessions by replaying it
+    /// through `flow`. Sessions that no longer fit the flow are dropped.
+    pub fn open(path: &Path, flow: &SurveyFlow) -> io::Result<Self> {
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(e),
+        };
+        let (memory, entries) = replay(&text, flow);
+        let mut journal = OpenOptions::new().create(true).append(true).open(path)?;
+        // Terminate a record torn by a crash so the next entry starts on its own line
+        if !text.is_empty() && !text.ends_with('\n') {
+            journal.write_all(b"\r\n")?;
+        }
+        Ok(Self { memory, path: path.to_path_buf(), journal, entries })
+    }
+
+    /// Journals an entry stamped with `at`, the time the in-memory session recorded
+    fn append(&mut self, at: SystemTime, op: &str, id: &str, arg: &str) {
+        let time = format_time(at);
+        let record = [time.as_str(), op, id, arg];
+        // A failed write only costs durability; the in-memory state is still right
+        match csv::Writer::new(&self.journal).write_record(&record) {
+            Ok(()) => self.entries += 1,
+            Err(e) => eprintln!("[ERROR] Failed to append to {}: {}", self.path.display(), e),
+        }
+    }
+
+    /// Journal entries needed to rebuild the current sessions
+    fn live_entries(&self) -> usize {
+        self.memory.iter().map(|(_, session)| 1 + session.button_presses().len()).sum()
+    }
+}
+
+impl SessionStore for FileStore {
+    fn get(&self, id: &str) -> Option<&UserSession> {
+        self.memory.get(id)
+    }
+
+    fn get_or_create(&mut self, id: &str, flow: &SurveyFlow, device: DeviceClass) -> &UserSession {
+        if self.memory.get(id).is_none() {
+            let started_at = self.memory.get_or_create(id, flow, device).started_at();
+            self.append(started_at, "new", id, device.as_str());
+        }
+        self.memory.get_or_create(id, flow, device)
+    }
+
+    fn reset(&mut self, id: &str, flow: &SurveyFlow) {
+        self.memory.reset(id, flow);
+        let session = self.memory.get(id).expect("session was just reset");
+        let (started_at, device) = (session.started_at(), session.device());
+        self.append(started_at, "new", id, device.as_str());
+    }
+
+    fn press_button(&mut self, id: &str, flow: &SurveyFlow, button: &str) -> ValidationResult<usize> {
+        self.get_or_create(id, flow, DeviceClass::Unknown);
+        let next_page = self.memory.press_button(id, flow, button)?;
+        // Only valid transitions are journaled, so replay never sees a rejected press
+        let pressed_at = self.memory.get(id).and_then(|s| s.press_times().last()).unwrap_or_else(SystemTime::now);
+        self.append(pressed_at, "press", id, button);
+        Ok(next_page)
+    }
+
+    fn touch(&mut self, id: &str) {
+        self.memory.touch(id);
+    }
+
+    fn remove(&mut self, id: &str) -> Option<UserSession> {
+        let session = self.memory.remove(id)?;
+        self.append(SystemTime::now(), "remove", id, "");
+        Some(session)
+    }
+
+    fn iter(&self) -> Box<dyn Iterator<Item = (&String, &UserSession)> + '_> {
+        self.memory.iter()
+    }
+
+    /// Rewrites the journal with just the live sessions once most of it is
+    /// history. The new journal is written beside the old one and renamed
+    /// over it, so a crash mid-compaction keeps the old journal intact.
+    fn compact(&mut self) -> io::Result<()> {
+        let live = self.live_entries();
+        if self.entries < COMPACT_MIN_ENTRIES || self.entries < live * 2 {
+            return Ok(());
+        }
+        let staging = self.path.with_extension("compacting");
+        let mut writer = csv::Writer::new(File::create(&staging)?);
+        for (id, session) in self.memory.iter() {
+            writer.write_record(&[format_time(session.started_at()).as_str(), "new", id, session.device().as_str()])?;
+            for (button, at) in session.button_presses().iter().zip(session.press_times()) {
+                writer.write_record(&[format_time(at).as_str(), "press", id, button])?;
+            }
+        }
+        fs::rename(&staging, &self.path)?;
+        self.journal = OpenOptions::new().append(true).open(&self.path)?;
+        println!("[DEBUG] Compacted {} from {} to {} entries", self.path.display(), self.entries, live);
+        self.entries = live;
+        Ok(())
+    }
+}
+
+/// A session's device, start time and timed button presses as read back
+type JournaledSession = (DeviceClass, SystemTime, Vec<(String, SystemTime)>);
+
+/// Rebuilds sessions from journal text. Returns them with the number of
+/// entries read.
+fn replay(text: &str, flow: &SurveyFlow) -> (MemoryStore, usize) {
+    // Device, start time and timed button presses per session, in journal order
+    let mut history: HashMap<String, JournaledSession> = HashMap::new();
+    let mut entries = 0;
+    for record in csv::Reader::new(text) {
+        let record = match record {
+            Ok(record) => record,
+            Err(e) => {
+                eprintln!("[WARN] Ignoring the rest of the session journal: {}", e);
+                break;
+            }
+        };
+        entries += 1;
+        let [time, op, id, arg] = record.as_slice() else {
+            println!("[DEBUG] Skipping malformed journal entry: {:?}", record);
+            continue;
+        };
+        let Some(time) = parse_time(time) else {
+            println!("[DEBUG] Skipping journal entry with bad timestamp: {:?}", record);
+            continue;
+        };
+        match op.as_str() {
+            "new" => {
+                history.insert(id.clone(), (DeviceClass::parse(arg), time, Vec::new()));
+            }
+            "press" => {
+                let (_, _, presses) = history.entry(id.clone()).or_insert((DeviceClass::Unknown, time, Vec::new()));
+                presses.push((arg.clone(), time));
+            }
+            "remove" => {
+                history.remove(id);
+            }
+            _ => println!("[DEBUG] Skipping journal entry with unknown op: {:?}", record),
+        }
+    }
+
+    let mut memory = MemoryStore::new();
+    for (id, (device, started_at, presses)) in history {
+        match UserSession::replay(flow, device, started_at, &presses) {
+            Ok(session) => {
+                memory.sessions.insert(id, session);
+            }
+            Err(e) => println!("[DEBUG] Dropping journaled session {}: {:?}", id, e),
+        }
+    }
+    (memory, entries)
+}
+
+/// Journal time: Unix seconds with three decimals
+fn format_time(time: SystemTime) -> String {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    format!("{}.{:03}", since_epoch.as_secs(), since_epoch.subsec_millis())
+}
+
+/// Reads `format_time` output, and the whole seconds older journals used
+fn parse_time(text: &str) -> Option<SystemTime> {
+    let (secs, millis) = text.split_once('.').unwrap_or((text, "0"));
+    let millis: u64 = match millis.len() {
+        1..=3 => millis.parse::<u64>().ok()? * 10u64.pow(3 - millis.len() as u32),
+        _ => return None,
+    };
+    Some(UNIX_EPOCH + Duration::from_secs(secs.parse().ok()?) + Duration::from_millis(millis))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_journal(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("hpom-{}-{}.journal", name, std::process::id()));
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    fn flow() -> SurveyFlow {
+        SurveyFlow::parse(include_str!("../flow.toml")).unwrap()
+    }
+
+    #[test]
+    fn file_store_survives_reopen() {
+        let (path, flow) = (temp_journal("reopen"), flow());
+        let (started_at, pressed_at) = {
+            let mut store = FileStore::open(&path, &flow).unwrap();
+            store.get_or_create("a", &flow, DeviceClass::Mobile);
+            store.press_button("a", &flow, "start").unwrap();
+            store.press_button("a", &flow, "ux").unwrap();
+            assert!(store.press_button("a", &flow, "nope").is_err());
+            store.press_button("b", &flow, "start").unwrap();
+            store.remove("b");
+            let a = store.get("a").unwrap();
+            (a.started_at(), a.press_times().collect::<Vec<_>>())
+        };
+        let store = FileStore::open(&path, &flow).unwrap();
+        let a = store.get("a").unwrap();
+        assert_eq!(a.button_presses(), ["start", "ux"]);
+        assert_eq!(a.current_page(), 3);
+        assert_eq!(a.device(), DeviceClass::Mobile);
+        // The journal keeps milliseconds
+        let millis = |t: SystemTime| t.duration_since(UNIX_EPOCH).unwrap().as_millis();
+        assert_eq!(millis(a.started_at()), millis(started_at));
+        assert_eq!(a.press_times().map(millis).collect::<Vec<_>>(), pressed_at.into_iter().map(millis).collect::<Vec<_>>());
+        assert_eq!(a.page_dwell_times().iter().map(|(page, _)| *page).collect::<Vec<_>>(), [1, 2]);
+        assert!(store.get("b").is_none());
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn reads_old_and_new_journal_times() {
+        assert_eq!(parse_time("1754489017"), Some(UNIX_EPOCH + Duration::from_secs(1_754_489_017)));
+        assert_eq!(parse_time("1754489017.5"), Some(UNIX_EPOCH + Duration::from_millis(1_754_489_017_500)));
+        let time = UNIX_EPOCH + Duration::from_millis(42_007);
+        assert_eq!(format_time(time), "42.007");
+        assert_eq!(parse_time(&format_time(time)), Some(time));
+        assert_eq!(parse_time("42.1234"), None);
+    }
+
+    #[test]
+    fn compaction_keeps_live_sessions_only() {
+        let (path, flow) = (temp_journal("compact"), flow());
+        let mut store = FileStore::open(&path, &flow).unwrap();
+        for i in 0..COMPACT_MIN_ENTRIES {
+            let id = format!("s{}", i);
+            store.press_button(&id, &flow, "start").unwrap();
+            store.remove(&id);
+        }
+        store.press_button("kept", &flow, "start").unwrap();
+        store.compact().unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap().lines().count(), 2);
+
+        store.press_button("kept", &flow, "ux").unwrap();
+        let reopened = FileStore::open(&path, &flow).unwrap();
+        assert_eq!(reopened.get("kept").unwrap().button_presses(), ["start", "ux"]);
+        assert_eq!(reopened.iter().count(), 1);
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn removes_idle_sessions_from_either_store() {
+        let (path, flow) = (temp_journal("idle"), flow());
+        let stores: [Box<dyn SessionStore>; 2] = [Box::new(MemoryStore::new()), Box::new(FileStore::open(&path, &flow).unwrap())];
+        for mut store in stores {
+            store.press_button("a", &flow, "start").unwrap();
+            store.press_button("b", &flow, "start").unwrap();
+            let ttl = Duration::from_secs(60);
+            let now = SystemTime::now();
+            assert!(store.remove_idle(now, ttl).is_empty());
+
+            let evicted = store.remove_idle(now + ttl * 2, ttl);
+            let mut ids: Vec<&str> = evicted.iter().map(|(id, _)| id.as_str()).collect();
+            ids.sort();
+            assert_eq!(ids, ["a", "b"]);
+            assert_eq!(evicted[0].1.button_presses(), ["start"]);
+            assert_eq!(store.iter().count(), 0);
+        }
+        assert_eq!(FileStore::open(&path, &flow).unwrap().iter().count(), 0);
+        fs::remove_file(path).unwrap();
+    }
+}
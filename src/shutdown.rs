@@ -0,0 +1,33 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static REQUESTED: AtomicBool = AtomicBool::new(false);
+
+const SIGINT: i32 = 2;
+const SIGTERM: i32 = 15;
+
+unsafe extern "C" {
+    // From libc, which std already links. The handler type is really
+    // `sighandler_t`; a plain function pointer has the same representation.
+    fn signal(signum: i32, handler: extern "C" fn(i32)) -> usize;
+}
+
+extern "C" fn on_signal(_signum: i32) {
+    // Only async-signal-safe work here: flip the flag and let the accept
+    // loop notice it
+    REQUESTED.store(true, Ordering::SeqCst);
+}
+
+/// Routes SIGINT and SIGTERM to the shutdown flag instead of killing the process
+pub fn install_handlers() {
+    for signum in [SIGINT, SIGTERM] {
+        // SAFETY: `on_signal` only touches an atomic, which is async-signal-safe
+        if unsafe { signal(signum, on_signal) } == usize::MAX {
+            eprintln!("[WARN] Could not install handler for signal {}", signum);
+        }
+    }
+}
+
+/// Whether a shutdown signal has arrived
+pub fn requested() -> bool {
+    REQUESTED.load(Ordering::SeqCst)
+}
@@ -0,0 +1,136 @@
+use crate::assets::{Asset, Assets};
+use crate::http::{self, Response};
+use crate::timestamp;
+use std::path::Path;
+use std::time::UNIX_EPOCH;
+
+/// Serves the assets in one directory. Request paths are percent-decoded
+/// and must name a file directly inside that directory tree: `.`, `..` and
+/// empty segments (including encoded ones like `%2e%2e`) are answered with
+/// 404, and on disk `Assets` also refuses anything a symlink resolves
+/// outside the asset root.
+pub struct StaticFiles {
+    dir: &'static str,
+    cache_control: &'static str,
+}
+
+impl StaticFiles {
+    /// Serves files under `dir` of the asset root; every response carries
+    /// `cache_control`
+    pub fn new(dir: &'static str, cache_control: &'static str) -> Self {
+        Self { dir, cache_control }
+    }
+
+    /// Serves `path`, relative to the directory and still percent-encoded.
+    /// `if_none_match` is the request's `If-None-Match` header.
+    pub fn serve(&self, assets: &Assets, path: &str, if_none_match: Option<&str>) -> Response {
+        let Some((name, asset)) = self.resolve(assets, path) else {
+            return Response::text("404 NOT FOUND", "");
+        };
+        // Size and mtime change whenever the file is replaced, which is all a
+        // validator needs; hashing every image on each request isn't worth it
+        let mtime = asset.modified.duration_since(UNIX_EPOCH).unwrap_or_default();
+        let etag = format!("\"{:x}-{:x}.{:x}\"", asset.bytes.len(), mtime.as_secs(), mtime.subsec_nanos());
+
+        let response = if if_none_match.is_some_and(|header| etag_matches(header, &etag)) {
+            Response::new("304 NOT MODIFIED", "", Vec::new())
+        } else {
+            Response::new("200 OK", content_type(&name), asset.bytes.into_owned())
+        };
+        response
+            .with_header("ETag", etag)
+            .with_header("Last-Modified", timestamp::format_http_date(asset.modified))
+            .with_header("Cache-Control", self.cache_control)
+    }
+
+    /// The asset `path` names, with its path relative to the asset root
+    fn resolve(&self, assets: &Assets, path: &str) -> Option<(String, Asset)> {
+        let decoded = http::percent_decode_path(path)?;
+        let clean = decoded
+            .split('/')
+            .all(|segment| !segment.is_empty() && segment != "." && segment != ".." && !segment.contains(['\\', '\0']));
+        if !clean {
+            return None;
+        }
+        let name = format!("{}/{}", self.dir, decoded);
+        let asset = assets.get(&name)?;
+        Some((name, asset))
+    }
+}
+
+/// Whether an `If-None-Match` header lists `etag` (or is `*`). Weak
+/// comparison, so `W/` prefixes added by proxies still match.
+fn etag_matches(header: &str, etag: &str) -> bool {
+    header
+        .split(',')
+        .map(|tag| tag.trim())
+        .any(|tag| tag == "*" || tag.trim_start_matches("W/") == etag)
+}
+
+fn content_type(path: &str) -> &'static str {
+    match Path::new(path).extension().and_then(|ext| ext.to_str()).map(str::to_ascii_lowercase).as_deref() {
+        Some("jpg" | "jpeg") => "image/jpeg",
+        Some("png") => "image/png",
+        Some("gif") => "image/gif",
+        Some("svg") => "image/svg+xml",
+        Some("ico") => "image/x-icon",
+        Some("css") => "text/css",
+        Some("js") => "application/javascript",
+        _ => "application/octet-stream",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn lib() -> StaticFiles {
+        StaticFiles::new("lib", "public, max-age=60")
+    }
+
+    #[test]
+    fn serves_files_with_validators() {
+        let response = lib().serve(&Assets::Embedded, "trophy.png", None);
+        assert_eq!(response.status, "200 OK");
+        assert_eq!(response.content_type, "image/png");
+        assert!(response.header("ETag").is_some());
+        assert!(response.header("Last-Modified").is_some_and(|date| date.ends_with(" GMT")));
+        assert_eq!(response.header("Cache-Control"), Some("public, max-age=60"));
+    }
+
+    #[test]
+    fn serves_the_stylesheet_as_css() {
+        let response = StaticFiles::new("static", "no-cache").serve(&Assets::Embedded, "app.css", None);
+        assert_eq!(response.status, "200 OK");
+        assert_eq!(response.content_type, "text/css");
+    }
+
+    #[test]
+    fn embedded_and_disk_agree() {
+        let disk = Assets::Disk(PathBuf::from("."));
+        let embedded = lib().serve(&Assets::Embedded, "trophy.png", None);
+        let on_disk = lib().serve(&disk, "trophy.png", None);
+        assert_eq!(embedded.body, on_disk.body);
+    }
+
+    #[test]
+    fn answers_matching_etag_with_304() {
+        let files = lib();
+        let etag = files.serve(&Assets::Embedded, "trophy.png", None).header("ETag").unwrap().to_string();
+        let response = files.serve(&Assets::Embedded, "trophy.png", Some(&format!("\"other\", W/{}", etag)));
+        assert_eq!(response.status, "304 NOT MODIFIED");
+        assert!(response.body.is_empty());
+        assert_eq!(files.serve(&Assets::Embedded, "trophy.png", Some("\"other\"")).status, "200 OK");
+    }
+
+    #[test]
+    fn stays_inside_the_directory() {
+        let files = lib();
+        let disk = Assets::Disk(PathBuf::from("."));
+        for path in ["../flow.toml", "%2e%2e/flow.toml", "..%2fflow.toml", "/../flow.toml", "./trophy.png", "", "missing.png", "%zz"] {
+            assert_eq!(files.serve(&disk, path, None).status, "404 NOT FOUND", "{}", path);
+            assert_eq!(files.serve(&Assets::Embedded, path, None).status, "404 NOT FOUND", "{}", path);
+        }
+    }
+}
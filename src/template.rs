@@ -0,0 +1,459 @@
+//! A small template language for the survey pages.
+//!
+//! - `{{ name }}` or `{{ item.field }}` inserts a value, HTML-escaped
+//! - `{% if name %}...{% else %}...{% endif %}` renders a branch; empty
+//!   strings, empty lists, `false` and missing values are false. `if not`
+//!   negates the test.
+//! - `{% for item in list %}...{% endfor %}` repeats its body per element
+//! - `{% include "partials/name.html" %}` renders another template in place,
+//!   with the same variables
+//! - `{% extends "partials/layout.html" %}`, as the first tag, renders that
+//!   layout instead; the page's `{% block name %}...{% endblock %}` sections
+//!   replace the layout's blocks of the same name, and nothing outside them
+//!   is allowed
+
+use std::collections::BTreeMap;
+use std::fmt;
+
+/// Data a template can refer to
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Str(String),
+    Bool(bool),
+    List(Vec<Value>),
+    Map(BTreeMap<String, Value>),
+}
+
+impl Value {
+    fn is_truthy(&self) -> bool {
+        match self {
+            Value::Str(s) => !s.is_empty(),
+            Value::Bool(b) => *b,
+            Value::List(items) => !items.is_empty(),
+            Value::Map(fields) => !fields.is_empty(),
+        }
+    }
+}
+
+impl From<&str> for Value {
+    fn from(s: &str) -> Self {
+        Value::Str(s.to_string())
+    }
+}
+
+impl From<String> for Value {
+    fn from(s: String) -> Self {
+        Value::Str(s)
+    }
+}
+
+impl From<bool> for Value {
+    fn from(b: bool) -> Self {
+        Value::Bool(b)
+    }
+}
+
+impl From<usize> for Value {
+    fn from(n: usize) -> Self {
+        Value::Str(n.to_string())
+    }
+}
+
+impl<V: Into<Value>> From<Vec<V>> for Value {
+    fn from(items: Vec<V>) -> Self {
+        Value::List(items.into_iter().map(Into::into).collect())
+    }
+}
+
+/// Top-level variables for a render, built up with `set`
+#[derive(Debug, Default)]
+pub struct Context {
+    vars: BTreeMap<String, Value>,
+}
+
+impl Context {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn set(&mut self, name: &str, value: impl Into<Value>) -> &mut Self {
+        self.vars.insert(name.to_string(), value.into());
+        self
+    }
+}
+
+/// Builds a `Value::Map` from field names and values
+pub fn map<V: Into<Value>>(fields: impl IntoIterator<Item = (&'static str, V)>) -> Value {
+    Value::Map(fields.into_iter().map(|(k, v)| (k.to_string(), v.into())).collect())
+}
+
+#[derive(Debug, PartialEq)]
+pub enum TemplateError {
+    Syntax(usize, String), // line, message
+    UnknownVariable(usize, String),
+    NotAList(usize, String),
+    MissingInclude(usize, String),
+    IncludeTooDeep(String),
+    DuplicateBlock(usize, String),
+}
+
+impl fmt::Display for TemplateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TemplateError::Syntax(line, msg) => write!(f, "line {}: {}", line, msg),
+            TemplateError::UnknownVariable(line, name) => write!(f, "line {}: unknown variable {}", line, name),
+            TemplateError::NotAList(line, name) => write!(f, "line {}: {} is not a list", line, name),
+            TemplateError::MissingInclude(line, name) => write!(f, "line {}: no template named {}", line, name),
+            TemplateError::IncludeTooDeep(name) => write!(f, "includes nested too deeply at {}", name),
+            TemplateError::DuplicateBlock(line, name) => write!(f, "line {}: block {} is defined twice", line, name),
+        }
+    }
+}
+
+/// Includes (and layouts extending layouts) deeper than this are assumed to
+/// be recursive
+const MAX_INCLUDE_DEPTH: usize = 8;
+
+/// Renders `source`. `load` fetches included templates by name.
+pub fn render(source: &str, context: &Context, load: &dyn Fn(&str) -> Option<String>) -> Result<String, TemplateError> {
+    let mut out = String::with_capacity(source.len());
+    let mut scopes = vec![context.vars.clone()];
+    render_source(source, &mut scopes, load, 0, &Blocks::new(), &mut out)?;
+    Ok(out)
+}
+
+/// Block bodies that override a layout's own, by block name
+type Blocks = BTreeMap<String, Vec<Node>>;
+
+/// Renders `source` with `blocks` overriding its blocks. If it extends a
+/// layout, its own blocks are added to `blocks` (without replacing the ones
+/// from further down the chain) and the layout is rendered instead.
+fn render_source(
+    source: &str,
+    scopes: &mut Vec<BTreeMap<String, Value>>,
+    load: &dyn Fn(&str) -> Option<String>,
+    depth: usize,
+    blocks: &Blocks,
+    out: &mut String,
+) -> Result<(), TemplateError> {
+    let nodes = parse(source)?;
+    let Some((line, layout)) = extended_layout(&nodes)? else {
+        return render_nodes(&nodes, scopes, load, depth, blocks, out);
+    };
+    if depth >= MAX_INCLUDE_DEPTH {
+        return Err(TemplateError::IncludeTooDeep(layout.to_string()));
+    }
+    let mut merged = blocks.clone();
+    let mut own = Vec::new();
+    for node in &nodes {
+        if let Node::Block { line, name, body } = node {
+            if own.contains(name) {
+                return Err(TemplateError::DuplicateBlock(*line, name.clone()));
+            }
+            own.push(name.clone());
+            merged.entry(name.clone()).or_insert_with(|| body.clone());
+        }
+    }
+    let source = load(layout).ok_or_else(|| TemplateError::MissingInclude(line, layout.to_string()))?;
+    render_source(&source, scopes, load, depth + 1, &merged, out)
+}
+
+/// The layout a template extends, after checking that `extends` is its first
+/// tag and that only whitespace and blocks follow it
+fn extended_layout(nodes: &[Node]) -> Result<Option<(usize, &str)>, TemplateError> {
+    let mut content = nodes.iter().filter(|node| !matches!(node, Node::Text(text) if text.trim().is_empty()));
+    let Some(Node::Extends { line, name }) = content.next() else {
+        return match nodes.iter().find_map(|node| if let Node::Extends { line, .. } = node { Some(*line) } else { None }) {
+            Some(line) => Err(TemplateError::Syntax(line, "extends must be the first tag".to_string())),
+            None => Ok(None),
+        };
+    };
+    if content.any(|node| !matches!(node, Node::Block { .. })) {
+        return Err(TemplateError::Syntax(*line, "only blocks can follow extends".to_string()));
+    }
+    Ok(Some((*line, name)))
+}
+
+#[derive(Debug, Clone)]
+enum Node {
+    Text(String),
+    Var { line: usize, path: String },
+    If { path: String, negate: bool, then: Vec<Node>, otherwise: Vec<Node> },
+    For { line: usize, var: String, path: String, body: Vec<Node> },
+    Include { line: usize, name: String },
+    Extends { line: usize, name: String },
+    Block { line: usize, name: String, body: Vec<Node> },
+}
+
+enum Token<'a> {
+    Text(&'a str),
+    Var(usize, &'a str),
+    Tag(usize, &'a str),
+}
+
+fn tokenize(source: &str) -> Result<Vec<Token<'_>>, TemplateError> {
+    let mut tokens = Vec::new();
+    let mut rest = source;
+    let mut line = 1;
+    while let Some(start) = [rest.find("{{"), rest.find("{%")].into_iter().flatten().min() {
+        let opener = &rest[start..];
+        let close = if opener.starts_with("{{") { "}}" } else { "%}" };
+        tokens.push(Token::Text(&rest[..start]));
+        line += rest[..start].matches('\n').count();
+        let end = opener
+            .find(close)
+            .ok_or_else(|| TemplateError::Syntax(line, format!("unclosed {}", &opener[..2])))?;
+        let inner = opener[2..end].trim();
+        tokens.push(if close == "}}" { Token::Var(line, inner) } else { Token::Tag(line, inner) });
+        line += opener[..end].matches('\n').count();
+        rest = &opener[end + 2..];
+    }
+    tokens.push(Token::Text(rest));
+    Ok(tokens)
+}
+
+fn parse(source: &str) -> Result<Vec<Node>, TemplateError> {
+    let tokens = tokenize(source)?;
+    let mut iter = tokens.into_iter();
+    let (nodes, end) = parse_block(&mut iter)?;
+    match end {
+        None => Ok(nodes),
+        Some((line, tag)) => Err(TemplateError::Syntax(line, format!("unexpected {{% {} %}}", tag))),
+    }
+}
+
+/// A block's nodes and the tag that ended it (with its line), or `None` at
+/// the end of the template
+type Block<'a> = (Vec<Node>, Option<(usize, &'a str)>);
+
+/// Parses nodes up to the next `else`, `endif`, `endfor` or `endblock` so the
+/// caller can check it closes the right block
+fn parse_block<'a>(tokens: &mut impl Iterator<Item = Token<'a>>) -> Result<Block<'a>, TemplateError> {
+    let mut nodes = Vec::new();
+    while let Some(token) = tokens.next() {
+        match token {
+            Token::Text(text) => nodes.push(Node::Text(text.to_string())),
+            Token::Var(line, path) => nodes.push(Node::Var { line, path: checked_path(line, path)? }),
+            Token::Tag(line, tag) => {
+                let words: Vec<&str> = tag.split_whitespace().collect();
+                match words.as_slice() {
+                    ["else"] | ["endif"] | ["endfor"] | ["endblock"] => return Ok((nodes, Some((line, tag)))),
+                    ["if", "not", path] | ["if", path] => {
+                        let negate = words.len() == 3;
+                        let path = checked_path(line, path)?;
+                        let (then, end) = parse_block(tokens)?;
+                        let otherwise = match end {
+                            Some((_, "else")) => match parse_block(tokens)? {
+                                (otherwise, Some((_, "endif"))) => otherwise,
+                                _ => return Err(TemplateError::Syntax(line, "if without endif".to_string())),
+                            },
+                            Some((_, "endif")) => Vec::new(),
+                            _ => return Err(TemplateError::Syntax(line, "if without endif".to_string())),
+                        };
+                        nodes.push(Node::If { path, negate, then, otherwise });
+                    }
+                    ["for", var, "in", path] => {
+                        let var = checked_path(line, var)?;
+                        let path = checked_path(line, path)?;
+                        let body = match parse_block(tokens)? {
+                            (body, Some((_, "endfor"))) => body,
+                            _ => return Err(TemplateError::Syntax(line, "for without endfor".to_string())),
+                        };
+                        nodes.push(Node::For { line, var, path, body });
+                    }
+                    ["include", name] => nodes.push(Node::Include { line, name: quoted(line, name)? }),
+                    ["extends", name] => nodes.push(Node::Extends { line, name: quoted(line, name)? }),
+                    ["block", name] => {
+                        let name = checked_path(line, name)?;
+                        let body = match parse_block(tokens)? {
+                            (body, Some((_, "endblock"))) => body,
+                            _ => return Err(TemplateError::Syntax(line, "block without endblock".to_string())),
+                        };
+                        nodes.push(Node::Block { line, name, body });
+                    }
+                    _ => return Err(TemplateError::Syntax(line, format!("unknown tag {{% {} %}}", tag))),
+                }
+            }
+        }
+    }
+    Ok((nodes, None))
+}
+
+/// The template name inside `"..."`
+fn quoted(line: usize, word: &str) -> Result<String, TemplateError> {
+    match word.strip_prefix('"').and_then(|w| w.strip_suffix('"')) {
+        Some(name) if !name.is_empty() => Ok(name.to_string()),
+        _ => Err(TemplateError::Syntax(line, format!("expected a quoted template name, got {}", word))),
+    }
+}
+
+fn checked_path(line: usize, path: &str) -> Result<String, TemplateError> {
+    let valid = !path.is_empty()
+        && path.split('.').all(|part| !part.is_empty() && part.chars().all(|c| c.is_ascii_alphanumeric() || c == '_'));
+    if valid {
+        Ok(path.to_string())
+    } else {
+        Err(TemplateError::Syntax(line, format!("invalid variable name {:?}", path)))
+    }
+}
+
+/// Looks up `a.b.c`, innermost scope first
+fn lookup<'a>(scopes: &'a [BTreeMap<String, Value>], path: &str) -> Option<&'a Value> {
+    let mut parts = path.split('.');
+    let first = parts.next()?;
+    let mut value = scopes.iter().rev().find_map(|scope| scope.get(first))?;
+    for part in parts {
+        match value {
+            Value::Map(fields) => value = fields.get(part)?,
+            _ => return None,
+        }
+    }
+    Some(value)
+}
+
+fn render_nodes(
+    nodes: &[Node],
+    scopes: &mut Vec<BTreeMap<String, Value>>,
+    load: &dyn Fn(&str) -> Option<String>,
+    depth: usize,
+    blocks: &Blocks,
+    out: &mut String,
+) -> Result<(), TemplateError> {
+    for node in nodes {
+        match node {
+            Node::Text(text) => out.push_str(text),
+            Node::Var { line, path } => match lookup(scopes, path) {
+                Some(Value::Str(s)) => out.push_str(&html_escape(s)),
+                Some(Value::Bool(b)) => out.push_str(if *b { "true" } else { "false" }),
+                Some(_) => return Err(TemplateError::Syntax(*line, format!("{} can't be printed", path))),
+                None => return Err(TemplateError::UnknownVariable(*line, path.clone())),
+            },
+            Node::If { path, negate, then, otherwise, .. } => {
+                let truthy = lookup(scopes, path).is_some_and(Value::is_truthy);
+                let branch = if truthy != *negate { then } else { otherwise };
+                render_nodes(branch, scopes, load, depth, blocks, out)?;
+            }
+            Node::For { line, var, path, body } => {
+                let items = match lookup(scopes, path) {
+                    Some(Value::List(items)) => items.clone(),
+                    Some(_) => return Err(TemplateError::NotAList(*line, path.clone())),
+                    None => return Err(TemplateError::UnknownVariable(*line, path.clone())),
+                };
+                for item in items {
+                    scopes.push(BTreeMap::from([(var.clone(), item)]));
+                    let result = render_nodes(body, scopes, load, depth, blocks, out);
+                    scopes.pop();
+                    result?;
+                }
+            }
+            Node::Include { line, name } => {
+                if depth >= MAX_INCLUDE_DEPTH {
+                    return Err(TemplateError::IncludeTooDeep(name.clone()));
+                }
+                let source = load(name).ok_or_else(|| TemplateError::MissingInclude(*line, name.clone()))?;
+                render_source(&source, scopes, load, depth + 1, &Blocks::new(), out)?;
+            }
+            Node::Block { name, body, .. } => {
+                let body = blocks.get(name).unwrap_or(body);
+                render_nodes(body, scopes, load, depth, blocks, out)?;
+            }
+            // Only valid as the first tag, where render_source handles it
+            Node::Extends { line, .. } => {
+                return Err(TemplateError::Syntax(*line, "extends must be the first tag".to_string()));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Escapes text for HTML (and SVG) content and attribute values
+pub fn html_escape(s: &str) -> String {
+    s.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;").replace('\'', "&#39;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn no_includes(_: &str) -> Option<String> {
+        None
+    }
+
+    fn render_with(source: &str, context: &Context) -> Result<String, TemplateError> {
+        render(source, context, &no_includes)
+    }
+
+    #[test]
+    fn substitutes_and_escapes_variables() {
+        let mut context = Context::new();
+        context.set("name", "<b>Engi & co</b>").set("answer", map([("role", "Engineer")]));
+        assert_eq!(
+            render_with("Hi {{ name }}, you chose {{answer.role}}.", &context).unwrap(),
+            "Hi &lt;b&gt;Engi &amp; co&lt;/b&gt;, you chose Engineer."
+        );
+    }
+
+    #[test]
+    fn leaves_css_braces_alone() {
+        let source = "body { margin: 0; } .a{color:red}";
+        assert_eq!(render_with(source, &Context::new()).unwrap(), source);
+    }
+
+    #[test]
+    fn renders_conditionals_and_loops() {
+        let mut context = Context::new();
+        context
+            .set("done", true)
+            .set("empty", Vec::<Value>::new())
+            .set("answers", vec![map([("q", "Role"), ("a", "UX")]), map([("q", "Size"), ("a", "3-5")])]);
+        let source = "{% if done %}yes{% else %}no{% endif %}|{% if not empty %}none{% endif %}|\
+                      {% for x in answers %}{{ x.q }}={{ x.a }};{% endfor %}|{% if missing %}x{% endif %}";
+        assert_eq!(render_with(source, &context).unwrap(), "yes|none|Role=UX;Size=3-5;|");
+    }
+
+    #[test]
+    fn renders_includes_with_the_same_variables() {
+        let load = |name: &str| match name {
+            "partials/a.html" => Some("[{{ v }}{% include \"partials/b.html\" %}]".to_string()),
+            "partials/b.html" => Some("!".to_string()),
+            "partials/loop.html" => Some("{% include \"partials/loop.html\" %}".to_string()),
+            _ => None,
+        };
+        let mut context = Context::new();
+        context.set("v", "x");
+        assert_eq!(render("<{% include \"partials/a.html\" %}>", &context, &load).unwrap(), "<[x!]>");
+        assert!(matches!(render("{% include \"partials/loop.html\" %}", &context, &load), Err(TemplateError::IncludeTooDeep(_))));
+        assert!(matches!(render("{% include \"nope\" %}", &context, &load), Err(TemplateError::MissingInclude(1, _))));
+    }
+
+    #[test]
+    fn fills_layout_blocks_from_the_page() {
+        let load = |name: &str| match name {
+            "layout.html" => Some("<title>{% block title %}Default{% endblock %}</title>{% block body %}{% endblock %}".to_string()),
+            "narrow.html" => Some("{% extends \"layout.html\" %}{% block body %}[{% block inner %}{% endblock %}]{% endblock %}".to_string()),
+            _ => None,
+        };
+        let mut context = Context::new();
+        context.set("v", "x");
+        let page = "{% extends \"layout.html\" %}\n{% block body %}{{ v }}{% endblock %}\n";
+        assert_eq!(render(page, &context, &load).unwrap(), "<title>Default</title>x");
+        let page = "{% extends \"narrow.html\" %}{% block inner %}{{ v }}{% endblock %}{% block title %}T{% endblock %}";
+        assert_eq!(render(page, &context, &load).unwrap(), "<title>T</title>[x]");
+
+        let stray = "{% extends \"layout.html\" %}text";
+        assert!(matches!(render(stray, &context, &load), Err(TemplateError::Syntax(1, _))));
+        let late = "a{% extends \"layout.html\" %}";
+        assert!(matches!(render(late, &context, &load), Err(TemplateError::Syntax(1, _))));
+        let twice = "{% extends \"layout.html\" %}{% block body %}{% endblock %}\n{% block body %}{% endblock %}";
+        assert_eq!(render(twice, &context, &load), Err(TemplateError::DuplicateBlock(2, "body".to_string())));
+    }
+
+    #[test]
+    fn reports_errors_with_line_numbers() {
+        let context = Context::new();
+        assert_eq!(render_with("a\n{{ nope }}", &context), Err(TemplateError::UnknownVariable(2, "nope".to_string())));
+        assert!(matches!(render_with("\n\n{% if x %}", &context), Err(TemplateError::Syntax(3, _))));
+        assert!(matches!(render_with("{% endfor %}", &context), Err(TemplateError::Syntax(1, _))));
+        assert!(matches!(render_with("{{ unclosed", &context), Err(TemplateError::Syntax(1, _))));
+    }
+}
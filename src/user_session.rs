@@ -1,7 +1,17 @@
+use crate::device::DeviceClass;
+use crate::flow::SurveyFlow;
+use crate::labels::LabelRegistry;
+use std::time::{Duration, SystemTime};
+
 #[derive(Debug, Clone)]
 pub struct UserSession {
     button_presses: Vec<String>,
+    /// The page each button was pressed on and when, parallel to `button_presses`
+    press_times: Vec<(usize, SystemTime)>,
     current_page: usize,
+    started_at: SystemTime,
+    last_activity: SystemTime,
+    device: DeviceClass,
 }
 
 #[derive(Debug, PartialEq)]
@@ -13,234 +23,103 @@ pub enum ValidationError {
 
 pub type ValidationResult<T> = Result<T, ValidationError>;
 
-impl UserSession {
-    /// Consumes self and returns a pseudo-document string describing the user's flow
-    pub fn to_doc_string(self) -> String {
-        let mut doc = String::new();
-        // Role selection (Page 2)
-        let role = self.button_presses.get(1).map(|s| match s.as_str() {
-            "pm" => "Product Manager",
-            "ux" => "UX Designer",
-            "engi" => "Engineer",
-            "dm" => "Deveveloper Manager",
-            _ => s.as_str(),
-        });
-
-        // Question type (Page 3)
-        let qtype = self.button_presses.get(2).map(|s| match s.as_str() {
-            "mc" => "Multiple Choice",
-            "tf" => "True/False",
-            _ => s.as_str(),
-        });
+/// How a recorded response ended
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ResponseStatus {
+    Completed,
+    Abandoned,
+    Restarted,
+}
 
-        // Multiple Choice answers (Pages 4, 6)
-        let mut mc_answers = vec![];
-        if let Some(qtype) = qtype {
-            if qtype == "Multiple Choice" {
-                // Page 4
-                if let Some(ans) = self.button_presses.get(3) {
-                    let team_size = match ans.as_str() {
-                        "4a" => "3-5 people",
-                        "4b" => "6-8 people",
-                        "4c" => "9-12 people",
-                        "4d" => "13-15 people",
-                        _ => "(unknown)",
-                    };
-                    mc_answers.push(format!("Preferred team size: {team_size}"));
-                }
-                // Page 6
-                if let Some(ans) = self.button_presses.get(4) {
-                    let role_pref = match ans.as_str() {
-                        "6a" => "Product Manager",
-                        "6b" => "Developer Manager",
-                        "6c" => "Engineer",
-                        "6d" => "UX Designer",
-                        _ => "(unknown)",
-                    };
-                    mc_answers.push(format!("Wants to see more: {role_pref}"));
-                }
-            }
+impl ResponseStatus {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ResponseStatus::Completed => "completed",
+            ResponseStatus::Abandoned => "abandoned",
+            ResponseStatus::Restarted => "restarted",
         }
+    }
+}
 
-        // True/False answers (Pages 5, 7)
-        let mut tf_answers = vec![];
-        if let Some(qtype) = qtype {
-            if qtype == "True/False" {
-                // Page 5
-                if let Some(ans) = self.button_presses.get(3) {
-                    let resp = match ans.as_str() {
-                        "5t" => "True",
-                        "5f" => "False",
-                        _ => "(unknown)",
-                    };
-                    tf_answers.push(format!("Believes HPOM has been live for two years: {resp}"));
-                }
-                // Page 7
-                if let Some(ans) = self.button_presses.get(4) {
-                    let resp = match ans.as_str() {
-                        "7t" => "True",
-                        "7f" => "False",
-                        _ => "(unknown)",
-                    };
-                    tf_answers.push(format!("Not intimidated by Richard Cai: {resp}"));
-                }
+impl UserSession {
+    /// Returns a pseudo-document string describing the user's flow
+    pub fn to_doc_string(&self, labels: &LabelRegistry) -> String {
+        let mut doc = String::from("User Response Summary:\n");
+        for (question, label) in labels.answers_for(&self.button_presses) {
+            if let Some(label) = label {
+                doc.push_str(&format!("- {}: {}\n", question.prompt, label));
             }
         }
-
-        // Compose doc string
-        doc.push_str("User Response Summary:\n");
-        if let Some(role) = role {
-            doc.push_str(&format!("- Role: {}\n", role));
-        }
-        if let Some(qtype) = qtype {
-            doc.push_str(&format!("- Question type: {}\n", qtype));
-        }
-        for ans in mc_answers {
-            doc.push_str(&format!("- {}\n", ans));
-        }
-        for ans in tf_answers {
-            doc.push_str(&format!("- {}\n", ans));
-        }
         doc
     }
-    pub fn new() -> Self {
-        Self { 
+    pub fn new(start_page: usize) -> Self {
+        Self::started(start_page, DeviceClass::Unknown, SystemTime::now())
+    }
+
+    /// A session that started at `started_at` on a `device`
+    pub fn started(start_page: usize, device: DeviceClass, started_at: SystemTime) -> Self {
+        Self {
             button_presses: Vec::new(),
-            current_page: 1,
+            press_times: Vec::new(),
+            current_page: start_page,
+            started_at,
+            last_activity: started_at,
+            device,
         }
     }
+
+    /// Rebuilds a session by replaying its timed button presses through
+    /// `flow`, so a restored session is only accepted if it is still valid
+    pub fn replay(
+        flow: &SurveyFlow,
+        device: DeviceClass,
+        started_at: SystemTime,
+        button_presses: &[(String, SystemTime)],
+    ) -> ValidationResult<Self> {
+        let mut session = Self::started(flow.start_page(), device, started_at);
+        for (button, at) in button_presses {
+            session.process_button_press_at(flow, button, *at)?;
+        }
+        Ok(session)
+    }
+
+    /// Record that the user just interacted with this session
+    pub fn touch(&mut self) {
+        self.last_activity = SystemTime::now();
+    }
+
+    /// How long since the user last interacted with this session
+    pub fn idle_time(&self, now: SystemTime) -> Duration {
+        now.duration_since(self.last_activity).unwrap_or_default()
+    }
     
-    /// Process a button press and return the next page if valid
-    /// HARDCODED FLOW LOGIC:
-    /// 1. Page 1: "start" -> Page 2
-    /// 2. Page 2: "pm"|"ux"|"engi"|"dm" -> Page 3
-    /// 3. Page 3: "mc" -> Page 4, "tf" -> Page 5
-    /// 4. Page 4: "4a"|"4b"|"4c"|"4d" -> Page 6 (only if "mc" was pressed)
-    /// 5. Page 5: "5t"|"5f" -> Page 7 (only if "tf" was pressed)
-    /// 6. Page 6: "6a"|"6b"|"6c"|"6d" -> Page 8 (only if "mc" was pressed)
-    /// 7. Page 7: "7t"|"7f" -> Page 8 (only if "tf" was pressed)
-    /// 8. Page 8: "trophy" -> Page 9
-    /// 9. Page 9: No button presses allowed
-    pub fn process_button_press(&mut self, button: &str) -> ValidationResult<usize> {
-        let next_page = match self.current_page {
-            1 => {
-                // Page 1: Only "start" allowed
-                if button == "start" {
-                    Ok(2)
-                } else {
-                    Err(ValidationError::InvalidButton(
-                        button.to_string(), 
-                        vec!["start".to_string()]
-                    ))
-                }
-            },
-            
-            2 => {
-                // Page 2: Role selection
-                match button {
-                    "pm" | "ux" | "engi" | "dm" => Ok(3),
-                    _ => Err(ValidationError::InvalidButton(
-                        button.to_string(),
-                        vec!["pm".to_string(), "ux".to_string(), "engi".to_string(), "dm".to_string()]
-                    ))
-                }
-            },
-            
-            3 => {
-                // Page 3: Multiple choice or True/False selection
-                match button {
-                    "mc" => Ok(4),
-                    "tf" => Ok(5),
-                    _ => Err(ValidationError::InvalidButton(
-                        button.to_string(),
-                        vec!["mc".to_string(), "tf".to_string()]
-                    ))
-                }
-            },
-            
-            4 => {
-                // Page 4: Multiple choice questions (only if "mc" was pressed)
-                if !self.button_presses.contains(&"mc".to_string()) {
-                    return Err(ValidationError::InvalidPage(4));
-                }
-                match button {
-                    "4a" | "4b" | "4c" | "4d" => Ok(6),
-                    _ => Err(ValidationError::InvalidButton(
-                        button.to_string(),
-                        vec!["4a".to_string(), "4b".to_string(), "4c".to_string(), "4d".to_string()]
-                    ))
-                }
-            },
-            
-            5 => {
-                // Page 5: True/False questions (only if "tf" was pressed)
-                if !self.button_presses.contains(&"tf".to_string()) {
-                    return Err(ValidationError::InvalidPage(5));
-                }
-                match button {
-                    "5t" | "5f" => Ok(7),
-                    _ => Err(ValidationError::InvalidButton(
-                        button.to_string(),
-                        vec!["5t".to_string(), "5f".to_string()]
-                    ))
-                }
-            },
-            
-            6 => {
-                // Page 6: More multiple choice questions (only if "mc" was pressed)
-                if !self.button_presses.contains(&"mc".to_string()) {
-                    return Err(ValidationError::InvalidPage(6));
-                }
-                match button {
-                    "6a" | "6b" | "6c" | "6d" => Ok(8),
-                    _ => Err(ValidationError::InvalidButton(
-                        button.to_string(),
-                        vec!["6a".to_string(), "6b".to_string(), "6c".to_string(), "6d".to_string()]
-                    ))
-                }
-            },
-            
-            7 => {
-                // Page 7: More true/false questions (only if "tf" was pressed)
-                if !self.button_presses.contains(&"tf".to_string()) {
-                    return Err(ValidationError::InvalidPage(7));
-                }
-                match button {
-                    "7t" | "7f" => Ok(8),
-                    _ => Err(ValidationError::InvalidButton(
-                        button.to_string(),
-                        vec!["7t".to_string(), "7f".to_string()]
-                    ))
-                }
-            },
-            
-            8 => {
-                // Page 8: Trophy button
-                if button == "trophy" {
-                    Ok(9)
-                } else {
-                    Err(ValidationError::InvalidButton(
-                        button.to_string(),
-                        vec!["trophy".to_string()]
-                    ))
-                }
-            },
-            
-            9 => {
-                // Page 9: No button presses allowed
-                Err(ValidationError::InvalidButton(
-                    button.to_string(),
-                    vec![] // No buttons allowed
-                ))
-            },
-            
-            _ => Err(ValidationError::NoTransitionDefined(self.current_page))
-        }?;
-        
+    /// Process a button press and return the next page if valid.
+    /// Allowed buttons, preconditions and transitions come from `flow`.
+    pub fn process_button_press(&mut self, flow: &SurveyFlow, button: &str) -> ValidationResult<usize> {
+        self.process_button_press_at(flow, button, SystemTime::now())
+    }
+
+    /// Like `process_button_press`, for a press that happened at `at`
+    pub fn process_button_press_at(&mut self, flow: &SurveyFlow, button: &str, at: SystemTime) -> ValidationResult<usize> {
+        let page = flow
+            .page(self.current_page)
+            .ok_or(ValidationError::NoTransitionDefined(self.current_page))?;
+
+        // Some pages are only valid on one branch, e.g. page 4 needs "mc"
+        if !page.requires().iter().all(|required| self.button_presses.contains(required)) {
+            return Err(ValidationError::InvalidPage(self.current_page));
+        }
+
+        let next_page = page
+            .next_page(button)
+            .ok_or_else(|| ValidationError::InvalidButton(button.to_string(), page.buttons()))?;
+
         // If we get here, the button press was valid
         self.button_presses.push(button.to_string());
+        self.press_times.push((self.current_page, at));
         self.current_page = next_page;
-        
+        self.last_activity = self.last_activity.max(at);
+
         Ok(next_page)
     }
     
@@ -253,4 +132,36 @@ impl UserSession {
     pub fn button_presses(&self) -> &[String] {
         &self.button_presses
     }
+
+    /// When each button was pressed, parallel to `button_presses`
+    pub fn press_times(&self) -> impl Iterator<Item = SystemTime> + '_ {
+        self.press_times.iter().map(|&(_, at)| at)
+    }
+
+    pub fn started_at(&self) -> SystemTime {
+        self.started_at
+    }
+
+    /// When the last button was pressed, if any was
+    pub fn finished_at(&self) -> Option<SystemTime> {
+        self.press_times.last().map(|&(_, at)| at)
+    }
+
+    pub fn device(&self) -> DeviceClass {
+        self.device
+    }
+
+    /// How long the user spent on each page before pressing its button:
+    /// from the session start (or the previous press) to the press
+    pub fn page_dwell_times(&self) -> Vec<(usize, Duration)> {
+        let mut arrived = self.started_at;
+        self.press_times
+            .iter()
+            .map(|&(page, at)| {
+                let dwell = at.duration_since(arrived).unwrap_or_default();
+                arrived = at;
+                (page, dwell)
+            })
+            .collect()
+    }
 }